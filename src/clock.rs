@@ -0,0 +1,153 @@
+use std::fmt::{Display, Formatter};
+use std::time::{Duration, Instant};
+
+use crate::model::Speed;
+
+/// The part of a wait that is spun instead of slept when timing precisely, to absorb the overshoot of `sleep`.
+const SPIN_THRESHOLD : Duration = Duration::from_micros(200);
+/// Sends later than this are counted as late in the timing statistics.
+const LATE_THRESHOLD : Duration = Duration::from_millis(1);
+
+/// Schedules packets against an absolute playback clock, so that timing errors do not accumulate.
+/// The clock is anchored at a capture timestamp and the instant it is played at; the deadline of
+/// any later packet follows from the time between its timestamp and the anchor, scaled by the speed.
+/// Without an anchor, the clock is anchored on the next call to `deadline`.
+pub(crate) struct PlaybackClock {
+    anchor: Option<(Instant, Duration)>,
+    precise: bool,
+}
+
+impl PlaybackClock {
+    /// With `precise` timing, the last part of every wait is spun to reach sub-100µs accuracy, at the cost of CPU time.
+    pub(crate) fn new(precise: bool) -> Self {
+        Self {
+            anchor: None,
+            precise,
+        }
+    }
+
+    /// Anchors the capture `timestamp` at the given instant.
+    pub(crate) fn anchor(&mut self, instant: Instant, timestamp: Duration) {
+        self.anchor = Some((instant, timestamp));
+    }
+
+    /// Drops the anchor, e.g. when playback is paused or jumps to another position.
+    pub(crate) fn reset(&mut self) {
+        self.anchor = None;
+    }
+
+    /// Re-anchors the clock at the current playback position, so that a change of speed only affects the packets to come.
+    /// A clock anchored in the future, e.g. during the gap between loop iterations, keeps its timestamp, and the
+    /// time remaining until it is scaled to the new speed.
+    pub(crate) fn change_speed(&mut self, from: Speed, to: Speed) {
+        let now = Instant::now();
+        self.anchor = match (self.anchor, from, to) {
+            (Some((instant, timestamp)), Speed::Factor(from), Speed::Factor(to)) if instant > now => {
+                Some((now + (instant - now).mul_f64(from / to), timestamp))
+            }
+            (Some((instant, timestamp)), Speed::Factor(factor), _) => {
+                Some((now, timestamp + now.saturating_duration_since(instant).mul_f64(factor)))
+            }
+            _ => { None }
+        };
+    }
+
+    /// The instant to send the packet with the given capture `timestamp` at. When the clock has no anchor,
+    /// it is anchored now at the `previous` timestamp. Returns `None` when playing as fast as possible.
+    pub(crate) fn deadline(&mut self, timestamp: Duration, previous: Duration, speed: Speed) -> Option<Instant> {
+        let (instant, anchor_timestamp) = *self.anchor.get_or_insert_with(|| (Instant::now(), previous));
+        match speed {
+            Speed::Factor(_) => { Some(instant + speed.scale(timestamp.saturating_sub(anchor_timestamp))) }
+            Speed::Max => { None }
+        }
+    }
+
+    /// Waits until the deadline has passed.
+    pub(crate) fn wait_until(&self, deadline: Instant) {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if !self.precise {
+            std::thread::sleep(remaining);
+            return;
+        }
+        if remaining > SPIN_THRESHOLD {
+            std::thread::sleep(remaining - SPIN_THRESHOLD);
+        }
+        while Instant::now() < deadline {
+            std::hint::spin_loop();
+        }
+    }
+}
+
+/// How late packets were sent compared to their scheduled time, over all packets that were played with recorded timing.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct TimingStatistics {
+    pub(crate) packets: usize,
+    pub(crate) late_packets: usize,
+    pub(crate) total_lateness: Duration,
+    pub(crate) max_lateness: Duration,
+}
+
+impl TimingStatistics {
+    pub(crate) fn record(&mut self, lateness: Duration) {
+        self.packets += 1;
+        if lateness > LATE_THRESHOLD {
+            self.late_packets += 1;
+        }
+        self.total_lateness += lateness;
+        self.max_lateness = self.max_lateness.max(lateness);
+    }
+
+    pub(crate) fn mean_lateness(&self) -> Duration {
+        if self.packets == 0 {
+            Duration::ZERO
+        } else { self.total_lateness.div_f64(self.packets as f64) }
+    }
+}
+
+impl Display for TimingStatistics {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} packets scheduled, mean lateness {:?}, max lateness {:?}, {} packets more than {:?} late",
+            self.packets, self.mean_lateness(), self.max_lateness, self.late_packets, LATE_THRESHOLD)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TIMESTAMP : Duration = Duration::from_secs(100);
+
+    fn anchored_at(offset: Duration, future: bool) -> PlaybackClock {
+        let mut clock = PlaybackClock::new(false);
+        let now = Instant::now();
+        clock.anchor(if future { now + offset } else { now - offset }, TIMESTAMP);
+        clock
+    }
+
+    #[test]
+    fn a_past_anchor_moves_to_the_current_playback_position() {
+        let mut clock = anchored_at(Duration::from_secs(10), false);
+        clock.change_speed(Speed::Factor(2.0), Speed::Factor(1.0));
+        let (instant, timestamp) = clock.anchor.expect("the clock stays anchored");
+        assert!(instant.saturating_duration_since(Instant::now()) < Duration::from_millis(100));
+        // 10 seconds at twice the speed played 20 seconds of the capture
+        assert!(timestamp >= TIMESTAMP + Duration::from_secs(20) && timestamp < TIMESTAMP + Duration::from_millis(20_200));
+    }
+
+    #[test]
+    fn a_future_anchor_keeps_its_timestamp_and_scales_the_remaining_wait() {
+        let mut clock = anchored_at(Duration::from_secs(10), true);
+        clock.change_speed(Speed::Factor(1.0), Speed::Factor(2.0));
+        let (instant, timestamp) = clock.anchor.expect("the clock stays anchored");
+        assert_eq!(timestamp, TIMESTAMP);
+        let remaining = instant.saturating_duration_since(Instant::now());
+        assert!(remaining > Duration::from_millis(4_900) && remaining <= Duration::from_secs(5));
+    }
+
+    #[test]
+    fn playing_as_fast_as_possible_drops_the_anchor() {
+        let mut clock = anchored_at(Duration::from_secs(10), true);
+        clock.change_speed(Speed::Max, Speed::Factor(1.0));
+        assert!(clock.anchor.is_none());
+    }
+}
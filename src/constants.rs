@@ -1,6 +1,7 @@
 pub(crate) const DEFAULT_DEST_PORT : u16 = 3000;
 pub(crate) const DEFAULT_SRC_PORT : u16 = 3000;
 pub(crate) const DEFAULT_TTL : u32 = 1;
+pub(crate) const DEFAULT_MAX_SOCKETS : usize = 64;
 
 pub(crate) const PLAYER_STARTUP_TIMEOUT_MS : u64 = 2000;
 
@@ -8,4 +9,12 @@ pub(crate) const ERROR_INCORRECT_FILE_PATH : i32 = 1;
 pub(crate) const ERROR_CREATE_PLAYER : i32 = 2;
 pub(crate) const ERROR_INIT_PLAYER_TIMEOUT : i32 = 3;
 pub(crate) const ERROR_INIT_PLAYER : i32 = 4;
-pub(crate) const ERROR_PARSE_FILE : i32 = 5;
\ No newline at end of file
+pub(crate) const ERROR_PARSE_FILE : i32 = 5;
+pub(crate) const ERROR_SELECT_PACKETS : i32 = 6;
+pub(crate) const ERROR_READ_FILE : i32 = 7;
+pub(crate) const ERROR_UNSUPPORTED_FILE : i32 = 8;
+pub(crate) const ERROR_CORRUPT_FILE : i32 = 9;
+pub(crate) const ERROR_SEND_PACKET : i32 = 10;
+pub(crate) const ERROR_COMMAND_CHANNEL : i32 = 11;
+pub(crate) const ERROR_DECOMPRESS_FILE : i32 = 12;
+pub(crate) const ERROR_REWRITE_RULES : i32 = 13;
@@ -1,28 +1,41 @@
+mod clock;
 mod model;
 mod player;
 mod constants;
 
 use std::{env, thread};
+use std::fmt::Debug;
 use std::fs::File;
 use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::PathBuf;
+use std::num::{NonZeroU32, NonZeroUsize};
 use std::process::exit;
+use std::str::FromStr;
 use std::sync::mpsc;
 use std::sync::mpsc::TryRecvError;
 use std::time::Duration;
 
 use clap::Parser;
-use dialoguer::Select;
+use dialoguer::{Input, Select};
 use dialoguer::console::Term;
 use dialoguer::theme::ColorfulTheme;
 use indicatif::{FormattedDuration, ProgressBar, ProgressStyle};
-use log::{error, info, trace};
+use log::{debug, error, info, trace, warn};
+use nom::number::Endianness;
 
+use clock::TimingStatistics;
 use player::Player;
-use constants::{DEFAULT_DEST_PORT, DEFAULT_SRC_PORT, DEFAULT_TTL};
-use crate::constants::{ERROR_CREATE_PLAYER, ERROR_INCORRECT_FILE_PATH, ERROR_INIT_PLAYER, ERROR_INIT_PLAYER_TIMEOUT, ERROR_PARSE_FILE, PLAYER_STARTUP_TIMEOUT_MS};
-use crate::model::pcap::Pcap;
-use crate::model::{Command, Event, PositionChange, Recording};
-use crate::player::PlayerState;
+use constants::{DEFAULT_DEST_PORT, DEFAULT_MAX_SOCKETS, DEFAULT_SRC_PORT, DEFAULT_TTL};
+use crate::constants::{ERROR_INCORRECT_FILE_PATH, ERROR_INIT_PLAYER_TIMEOUT, PLAYER_STARTUP_TIMEOUT_MS};
+use crate::model::{Command, Destination, Error, Event, ParseError, PositionChange, Recording, SeekTarget, SourcePort, Speed, parse_duration};
+use crate::model::packet::{ip_protocol_name, parse_ip_protocol};
+use crate::model::pcap::PcapMagicNumber;
+use crate::model::filter::Filter;
+use crate::model::multicast::InterfaceSelector;
+use crate::model::rewrite::RewriteRules;
+use crate::model::playlist::{Playlist, PlaylistStatistics, TruncationPolicy};
+use crate::model::recovery::DamageKind;
+use crate::player::{Looping, PlayerState};
 
 #[derive(Parser, Debug)]
 #[clap(name = "packet-play")]
@@ -30,18 +43,101 @@ use crate::player::PlayerState;
 struct Cli {
     file: String,
     #[clap(parse(try_from_str))]
-    #[clap(short, long, default_value_t = SocketAddr::new(IpAddr::V4(Ipv4Addr::BROADCAST),DEFAULT_DEST_PORT))]
-    destination: SocketAddr,
-    #[clap(short = 's', long = "source", default_value_t = DEFAULT_SRC_PORT)]
-    source_port: u16,
+    /// Where to send the packets. Use '*' for the address and/or port to keep those of each captured packet, e.g. '*', '*:3000' or '239.1.1.1:*'.
+    #[clap(short, long, default_value_t = Destination::from(SocketAddr::new(IpAddr::V4(Ipv4Addr::BROADCAST),DEFAULT_DEST_PORT)))]
+    destination: Destination,
+    /// A file with rules that rewrite the destination of packets, one '<pattern> -> <destination>' rule per line.
+    #[clap(long)]
+    rewrite: Option<PathBuf>,
+    /// The port to send from. Use '*' to keep the source port of each captured packet, with a socket for every port.
+    #[clap(short = 's', long = "source", parse(try_from_str), default_value_t = SourcePort::Fixed(DEFAULT_SRC_PORT))]
+    source_port: SourcePort,
+    /// The most sockets kept open at once when sending from several source ports; the least recently used one is closed to make room.
+    #[clap(long, default_value_t = NonZeroUsize::new(DEFAULT_MAX_SOCKETS).expect("the default is not zero"))]
+    max_sockets: NonZeroUsize,
+    /// The TTL (IPv4) or hop limit (IPv6) of the packets, for unicast, broadcast and multicast destinations alike.
     #[clap(short, long, default_value_t = DEFAULT_TTL)]
     ttl: u32,
+    /// The interface to send multicast packets from, by name (eth1) or by one of its addresses.
+    #[clap(long, parse(try_from_str))]
+    multicast_interface: Option<InterfaceSelector>,
+    /// Do not loop multicast packets back to listeners on this host.
+    #[clap(long)]
+    multicast_loop_disable: bool,
+    /// Playback speed relative to the recorded timing, from 0.1 to 100, or 'max' to ignore the timestamps.
+    #[clap(long, parse(try_from_str), default_value_t = Speed::Factor(1.0))]
+    speed: Speed,
+    /// Replay the recording N times, or endlessly when N is omitted.
+    #[clap(short, long = "loop", value_name = "N")]
+    loop_count: Option<Option<NonZeroU32>>,
+    /// Pause between loop iterations, e.g. 500ms or 2s.
+    #[clap(long, parse(try_from_str = parse_duration), default_value = "0s")]
+    loop_gap: Duration,
+    /// Keep the playback time increasing across loop iterations, instead of restarting it at zero.
+    #[clap(long)]
+    loop_monotonic: bool,
+    /// Spin instead of sleep for the last part of every wait, for sub-100µs timing accuracy at the cost of CPU time.
+    #[clap(long)]
+    precise_timing: bool,
     #[clap(short, long)]
     auto_play_disable: bool,
+    /// Skip corrupt regions of the recording instead of refusing to play it.
+    #[clap(short, long)]
+    recover: bool,
+    /// Only replay packets captured on these interfaces (.pcapng only), by section:id as listed at startup, or by id or name in every section.
+    #[clap(short, long = "interface")]
+    interfaces: Vec<String>,
+    /// Only replay packets of these IP protocols, by name (udp, tcp, icmp, ...) or number.
+    /// Packets of protocols other than UDP are sent as UDP datagrams holding their raw IP payload, including the
+    /// transport header. Only UDP ports are captured, so these go to the port of the destination or rewrite rule.
+    #[clap(short, long = "protocol", parse(try_from_str = parse_ip_protocol), default_value = "udp")]
+    protocols: Vec<u8>,
+    /// Only replay the packets matching a filter expression, e.g. "udp dst port 3000 and src host 10.0.0.5",
+    /// "vlan 100", "net 10.1.0.0/16" or "payload[0] = 0xcafe". Primitives combine with and, or, not and parentheses.
+    #[clap(short, long, parse(try_from_str))]
+    filter: Option<Filter>,
+    /// Start replaying at a packet number (120, the frame number as listed by e.g. Wireshark), a time offset from the first packet (+10m) or a capture timestamp in unix seconds (@1650000000.5).
+    #[clap(long, parse(try_from_str), allow_hyphen_values = true)]
+    start: Option<SeekTarget>,
+    /// Stop replaying after a packet number (9000), a time offset from the first packet (+15m), a time offset before the last packet (-5m) or a capture timestamp.
+    #[clap(long, parse(try_from_str), allow_hyphen_values = true)]
+    end: Option<SeekTarget>,
+    /// What to do with packets that were truncated by the snap length while capturing: skip, truncate or pad (with zeros).
+    #[clap(long, parse(try_from_str), default_value_t = TruncationPolicy::Truncate)]
+    truncated: TruncationPolicy,
+    /// The directory to decompress a compressed recording into, instead of the system's temporary directory.
+    /// The temporary file takes as much disk space as the uncompressed recording, and is removed on exit.
+    #[clap(long)]
+    temp_dir: Option<PathBuf>,
 }
 
 const SELECT_UNSUPPORTED_KEY_INPUT: usize = 99;
 
+/// Creates the command for the item the user selected.
+type CommandConstructor = fn() -> Command;
+
+/// The commands as listed to the user, each with the function that creates the command once it is selected.
+/// Seeking, changing speed and stepping need a value, which the user is asked for first.
+const COMMANDS: [(&str, CommandConstructor); 8] = [
+    ("Play", || Command::Play),
+    ("Pause", || Command::Pause),
+    ("Rewind", || Command::Rewind),
+    ("Seek", || {
+        prompt::<SeekTarget>("Seek to (packet number, +/-offset or @capture timestamp)")
+            .map_or(Command::Unspecified, Command::Seek)
+    }),
+    ("Speed", || {
+        prompt::<Speed>("Speed (factor from 0.1 to 100, or max)")
+            .map_or(Command::Unspecified, Command::Speed)
+    }),
+    ("Step", || {
+        prompt::<NonZeroUsize>("Step (number of packets to send)")
+            .map_or(Command::Unspecified, |steps| Command::Step(steps.get()))
+    }),
+    ("Quit", || Command::Quit),
+    ("", || Command::Unspecified), // empty line for the progress bar
+];
+
 fn main() {
     if env::var("RUST_LOG").is_err() {
         env::set_var("RUST_LOG", "info");
@@ -54,8 +150,38 @@ fn main() {
     info!("\t Recording:\t{}", cli.file);
     info!("\t Destination:\t{}", cli.destination);
     info!("\t Source port:\t{}", cli.source_port);
+    if cli.source_port == SourcePort::Original || cli.rewrite.is_some() {
+        info!("\t Max sockets:\t{}", cli.max_sockets);
+    }
     info!("\t TTL:\t\t{}", cli.ttl);
+    info!("\t Multicast:\t{}, loopback {}",
+        cli.multicast_interface.as_ref().map_or_else(|| "default interface".to_string(), |interface| format!("interface {interface}")),
+        if cli.multicast_loop_disable { "disabled" } else { "enabled" });
+    info!("\t Speed:\t\t{}", cli.speed);
+    info!("\t Precise timing:\t{}", cli.precise_timing);
+    if let Some(loop_count) = cli.loop_count {
+        info!("\t Loop:\t\t{}, {} apart{}",
+            loop_count.map_or_else(|| "endless".to_string(), |count| format!("{count} times")),
+            FormattedDuration(cli.loop_gap),
+            if cli.loop_monotonic { ", monotonic playback time" } else { "" });
+    }
     info!("\t Auto play:\t{}", !cli.auto_play_disable);
+    info!("\t Recover:\t{}", cli.recover);
+    if !cli.interfaces.is_empty() {
+        info!("\t Interfaces:\t{}", cli.interfaces.join(", "));
+    }
+    info!("\t Protocols:\t{}", cli.protocols.iter()
+        .map(|protocol| ip_protocol_name(*protocol).map_or_else(|| protocol.to_string(), str::to_string))
+        .collect::<Vec<String>>().join(", "));
+    info!("\t Truncated:\t{}", cli.truncated);
+    if let Some(filter) = &cli.filter {
+        info!("\t Filter:\t{filter}");
+    }
+    if cli.start.is_some() || cli.end.is_some() {
+        info!("\t Window:\t{} - {}",
+            cli.start.map_or_else(|| "first".to_string(), |start| start.to_string()),
+            cli.end.map_or_else(|| "last".to_string(), |end| end.to_string()));
+    }
 
     let file_path = std::path::Path::new(cli.file.as_str());
     if !file_path.is_file() || !file_path.exists() {
@@ -63,44 +189,97 @@ fn main() {
         exit(ERROR_INCORRECT_FILE_PATH);
     };
 
-    let file = File::open(file_path).unwrap();
-    let recording = Pcap::try_from(file);
+    let file = File::open(file_path).unwrap_or_else(|err| exit_with(Error::ReadFile(err)));
+    let mut decompress_progress: Option<ProgressBar> = None;
+    let recording = Recording::open(file, cli.temp_dir.as_deref(), &mut |read, total| {
+        decompress_progress.get_or_insert_with(|| {
+            let progress_bar = ProgressBar::new(total);
+            progress_bar.set_style(ProgressStyle::default_bar()
+                .template("Decompressing [{wide_bar:.cyan/blue}] {bytes}/{total_bytes} ({eta})")
+                .progress_chars("#>-"));
+            progress_bar
+        }).set_position(read);
+    });
+    if let Some(progress_bar) = decompress_progress {
+        progress_bar.finish_and_clear();
+    }
+
+    if let Ok(mut recording) = recording {
+        if let Err(err) = check_damage(&recording, cli.recover) {
+            exit_with(err);
+        }
+        log_format(&recording);
+        log_interfaces(&recording);
+        if !cli.interfaces.is_empty() {
+            if let Err(err) = recording.select_interfaces(&cli.interfaces) {
+                exit_with(err);
+            }
+        }
+
+        let mut playlist = Playlist::new(&recording, cli.protocols.clone(), cli.filter.clone(), cli.truncated);
+        if let Err(err) = playlist.select_window(&recording, cli.start, cli.end) {
+            exit_with(err);
+        }
+        log_truncated_packets("packets in the window", playlist.truncated_records(&recording), cli.truncated);
 
-    if let Ok(recording) = recording {
         let (cmd_sender, cmd_receiver) = mpsc::channel();
         let input_cmd_sender = cmd_sender.clone();
         let (event_sender, event_receiver) = mpsc::channel();
         let input_event_sender = event_sender.clone();
 
-        let progress_bar = ProgressBar::new(recording.packets.len() as u64);
+        let progress_bar = ProgressBar::new(playlist.window().len() as u64);
 
         progress_bar.set_style(ProgressStyle::default_bar()
             .template("{msg} [{wide_bar:.cyan/blue}] {pos:>7}/{len:7}")
             .progress_chars("#>-"));
         progress_bar.set_draw_rate(10);
 
-        // TODO handle errors on creation of player
-        let player_handle = match Player::builder()
-            .recording(Recording::PCAP(recording))
+        let rewrite_rules = cli.rewrite.as_deref().map(RewriteRules::load).transpose()
+            .unwrap_or_else(|err| exit_with(err.into()));
+        if let Some(rules) = &rewrite_rules {
+            log_rewrite_rules(rules);
+        }
+
+        let mut player_builder = Player::builder()
+            .recording(recording)
+            .playlist(playlist)
             .destination(cli.destination)
             .source_port(cli.source_port)
+            .max_sockets(cli.max_sockets)
             .ttl(cli.ttl)
+            .multicast_loop(!cli.multicast_loop_disable)
+            .speed(cli.speed)
+            .looping(looping(&cli))
+            .precise_timing(cli.precise_timing)
             .cmd_rx(cmd_receiver)
-            .event_tx(event_sender)
-            .build() {
+            .event_tx(event_sender);
+        if let Some(rules) = rewrite_rules {
+            player_builder = player_builder.rewrite_rules(rules);
+        }
+        if let Some(interface) = cli.multicast_interface.clone() {
+            player_builder = player_builder.multicast_interface(interface);
+        }
+        // packets are read while playing, so building the player only reads up to the first packet to replay
+        let player_handle = match player_builder.build() {
             Ok(handle) => { handle }
-            Err(err) => { error!("{err:?}"); exit(ERROR_CREATE_PLAYER); }
+            Err(err) => {
+                while let Ok(Event::PlaylistReport(statistics)) = event_receiver.try_recv() {
+                    log_playlist(&statistics, &cli);
+                }
+                exit_with(err);
+            }
         };
         let input_handle = thread::spawn(move || {
             loop {
                 let selection = Select::with_theme(&ColorfulTheme::default())
-                    .items(&Command::as_vec())
+                    .items(&COMMANDS.map(|(label, _)| label))
                     .default(0)
                     .report(true)
                     .clear(true)
                     .interact_on_opt(&Term::stdout()).expect("inner").unwrap_or(SELECT_UNSUPPORTED_KEY_INPUT);
 
-                let command = Command::from(selection);
+                let command = COMMANDS.get(selection)
+                    .map_or(Command::Unspecified, |(_, command)| command());
                 if let Err(_err) = input_cmd_sender.send(command) {
                     break;
                 }
@@ -114,13 +293,14 @@ fn main() {
             match event_receiver.recv_timeout(Duration::from_secs(PLAYER_STARTUP_TIMEOUT_MS)) {
                 Ok(event) => {
                     match event {
-                        Event::Error(_) => { exit(ERROR_INIT_PLAYER) }
+                        Event::Error(err) => { exit_with(err) }
                         Event::PlayerReady => {
                             break; }
                         _ => { trace!("Unexpected to see this event here..."); }
                     }
                 }
                 Err(_) => {
+                    error!("Player did not start in time.");
                     exit(ERROR_INIT_PLAYER_TIMEOUT)
                 }
             }
@@ -132,6 +312,7 @@ fn main() {
 
         let mut current_state = PlayerState::Initial;
         let mut current_position = PositionChange::default();
+        let mut current_speed = cli.speed;
 
         loop {
             let data_updated = match event_receiver.try_recv() {
@@ -143,10 +324,31 @@ fn main() {
                 }
                 Ok(Event::PlayerPositionChanged(position)) => {
                     current_position = position;
+                    progress_bar.set_length(current_position.max_position as u64);
                     progress_bar.set_position(current_position.position as u64);
                     true
                 }
-                Ok(Event::Error(error)) => { trace!("{error:?}"); false }
+                Ok(Event::PlayerSpeedChanged(speed)) => {
+                    current_speed = speed;
+                    true
+                }
+                Ok(Event::TimingReport(timing)) => {
+                    log_timing(&timing);
+                    false
+                }
+                Ok(Event::PlaylistReport(statistics)) => {
+                    log_playlist(&statistics, &cli);
+                    false
+                }
+                Ok(Event::PacketStepped(packet)) => {
+                    progress_bar.println(format!("Stepped packet {} captured at {:.6}, sent {} bytes",
+                        packet.index + 1, packet.timestamp.as_secs_f64(), packet.length));
+                    false
+                }
+                Ok(Event::Error(err)) => {
+                    progress_bar.abandon();
+                    exit_with(err);
+                }
                 Err(TryRecvError::Empty) => { false }
                 Err(TryRecvError::Disconnected) => {
                     trace!("Event channel disconnected, Player stopped working. Exiting.");
@@ -155,7 +357,18 @@ fn main() {
             };
 
             if data_updated {
-                progress_bar.set_message(format!("{} [{}]", current_state, FormattedDuration(current_position.time_position)));
+                let iteration = match cli.loop_count {
+                    Some(Some(count)) => { format!(" loop {}/{}", current_position.iteration, count) }
+                    Some(None) => { format!(" loop {}", current_position.iteration) }
+                    None => { String::new() }
+                };
+                let matched = match cli.filter {
+                    Some(_) => { format!(" matched {}", current_position.matched) }
+                    None => { String::new() }
+                };
+                progress_bar.set_message(format!("{} [{}/{}] {}{}{}", current_state,
+                    FormattedDuration(current_position.time_position), FormattedDuration(current_position.time_total),
+                    current_speed, iteration, matched));
             }
             else {
                 progress_bar.tick();
@@ -164,9 +377,187 @@ fn main() {
 
         player_handle.join().expect("Player thread failed.");
         input_handle.join().expect("Input thread failed.");
+        // the player reports its timing when it quits, after the user interface stopped listening
+        while let Ok(event) = event_receiver.try_recv() {
+            match event {
+                Event::PlaylistReport(statistics) => { log_playlist(&statistics, &cli); }
+                Event::TimingReport(timing) => { log_timing(&timing); }
+                _ => {}
+            }
+        }
     } else {
-        let error = recording.unwrap_err();
-        error!("Cannot play recording, because: {:?}", error);
-        exit(ERROR_PARSE_FILE);
+        exit_with(recording.unwrap_err());
     };
-}
\ No newline at end of file
+}
+
+fn looping(cli: &Cli) -> Looping {
+    match cli.loop_count {
+        Some(iterations) => {
+            Looping {
+                iterations: iterations.map(NonZeroU32::get),
+                gap: cli.loop_gap,
+                monotonic: cli.loop_monotonic,
+            }
+        }
+        None => { Looping::default() }
+    }
+}
+
+/// Asks the user for the value of a command. Returns `None` when the prompt is aborted.
+fn prompt<T>(prompt: &str) -> Option<T>
+    where T: Clone + ToString + FromStr, T::Err: Debug + ToString {
+    Input::<T>::with_theme(&ColorfulTheme::default())
+        .with_prompt(prompt)
+        .interact_text_on(&Term::stdout())
+        .ok()
+}
+
+/// Logs the error along with the errors that caused it, and exits with the exit code for its kind.
+fn exit_with(err: Error) -> ! {
+    let mut message = err.to_string();
+    let mut source = std::error::Error::source(&err);
+    while let Some(cause) = source {
+        message = format!("{message}: {cause}");
+        source = cause.source();
+    }
+    error!("{message}.");
+    // the selection prompt may have hidden the cursor
+    let _ = Term::stdout().show_cursor();
+    exit(err.exit_code());
+}
+
+/// Reports the damaged regions of the recording. A file cut off at the end can always be played,
+/// skipping corrupt regions within the file needs the consent of the user.
+fn check_damage(recording: &Recording, recover: bool) -> Result<(), Error> {
+    for damage in recording.damage() {
+        match damage.kind {
+            DamageKind::Truncated { .. } => { warn!("Recording is cut off: {damage}."); }
+            DamageKind::Skipped { .. } if recover => { warn!("Recovered from {damage}."); }
+            DamageKind::Skipped { .. } => { return Err(ParseError::Corrupt(*damage).into()); }
+        }
+    }
+    Ok(())
+}
+
+fn byte_order(endianness: Endianness) -> &'static str {
+    match endianness {
+        Endianness::Big => { "big endian" }
+        Endianness::Little => { "little endian" }
+        Endianness::Native => { "native byte order" }
+    }
+}
+
+fn log_format(recording: &Recording) {
+    match recording {
+        Recording::PCAP(pcap) => {
+            let header = &pcap.header;
+            let precision = match header.magic_number {
+                PcapMagicNumber::Micros => { "microsecond timestamps" }
+                PcapMagicNumber::Nanos => { "nanosecond timestamps" }
+                PcapMagicNumber::ModifiedMicros => { "modified format, microsecond timestamps" }
+            };
+            info!("Format:\t\t pcap {}.{}, {}, {}, link type {}, snap length {}",
+                header.major_version, header.minor_version, byte_order(header.endianness), precision,
+                header.link_type & 0x0000FFFF, header.snap_len);
+        }
+        Recording::PCAPNG(pcapng) => {
+            for (section_index, section) in pcapng.sections.iter().enumerate() {
+                let header = &section.header;
+                let captured_with = [&header.application, &header.os, &header.hardware].into_iter()
+                    .flatten().cloned().collect::<Vec<String>>();
+                info!("Format:\t\t section {} pcapng {}.{}, {}{}",
+                    section_index, header.major_version, header.minor_version, byte_order(header.endianness),
+                    if captured_with.is_empty() { String::new() } else { format!(", captured with {}", captured_with.join(", ")) });
+                for record in section.name_resolutions.iter().flat_map(|block| &block.records) {
+                    debug!("\t {}\t{}", record.address, record.names.join(", "));
+                }
+            }
+        }
+    }
+    if !recording.in_capture_order() {
+        warn!("Packets are not in capture order, a time in the recording selects the first packet captured at or after it in file order.");
+    }
+}
+
+fn log_interfaces(recording: &Recording) {
+    if let Recording::PCAPNG(pcapng) = recording {
+        info!("Interfaces:");
+        for (section_index, section) in pcapng.sections.iter().enumerate() {
+            for (id, interface) in section.interfaces.iter().enumerate() {
+                let statistics = pcapng.interface_statistics(section_index, id as u32)
+                    .map(|statistics| format!(", {} packets received, {} dropped while capturing",
+                        statistics.received.map_or_else(|| "?".to_string(), |count| count.to_string()),
+                        statistics.dropped.map_or_else(|| "?".to_string(), |count| count.to_string())))
+                    .unwrap_or_default();
+                info!("\t {}:{}\t{}\t link type {}, timestamps in {}{}",
+                    section_index, id,
+                    interface.name.as_deref().unwrap_or("-"),
+                    interface.link_type,
+                    interface.ts_resolution,
+                    statistics);
+            }
+        }
+    }
+}
+
+/// Logs which of the packets read from the recording were replayed, and why the others were skipped.
+fn log_playlist(statistics: &PlaylistStatistics, cli: &Cli) {
+    log_skipped_packets(statistics);
+    log_filter_matches(statistics, cli.filter.as_ref());
+    log_truncated_packets("of the packets read", statistics.truncated, cli.truncated);
+    log_incomplete_datagrams(statistics);
+}
+
+fn log_skipped_packets(statistics: &PlaylistStatistics) {
+    info!("Replayed {} datagrams from {} packets read.", statistics.replayed, statistics.read);
+    if !statistics.skipped.is_empty() {
+        info!("Skipped packets:");
+        for (reason, count) in statistics.skipped.iter() {
+            info!("\t {}\t{}", count, reason);
+        }
+    }
+}
+
+fn log_rewrite_rules(rules: &RewriteRules) {
+    info!("Rewrite rules:");
+    for rule in rules.rules.iter() {
+        info!("\t {rule}");
+    }
+}
+
+fn log_filter_matches(statistics: &PlaylistStatistics, filter: Option<&Filter>) {
+    if let Some(filter) = filter {
+        info!("Filter {filter} matched {} packets.", statistics.matched);
+    }
+}
+
+/// Warns about the `count` truncated packets, which are described by `which`.
+fn log_truncated_packets(which: &str, count: usize, policy: TruncationPolicy) {
+    if count > 0 {
+        let action = match policy {
+            TruncationPolicy::Skip => { "skipping them" }
+            TruncationPolicy::Truncate => { "replaying the captured part" }
+            TruncationPolicy::Pad => { "padding them with zeros to their original length" }
+        };
+        warn!("{count} {which} are truncated by the snap length of the capture, {action} (see --truncated).");
+    }
+}
+
+fn log_timing(timing: &TimingStatistics) {
+    if timing.packets > 0 {
+        info!("Timing: {timing}.");
+    }
+}
+
+fn log_incomplete_datagrams(statistics: &PlaylistStatistics) {
+    if !statistics.incomplete_datagrams.is_empty() {
+        warn!("Skipped {} datagrams of which not all fragments are captured:", statistics.incomplete_datagrams.len());
+        for datagram in statistics.incomplete_datagrams.iter() {
+            warn!("\t {} -> {} (protocol {}, id {:#x}): {} fragments, {} bytes, starting at packet {}",
+                datagram.key.source, datagram.key.destination,
+                datagram.key.protocol, datagram.key.identification,
+                datagram.fragments, datagram.received_bytes,
+                datagram.first_record + 1);
+        }
+    }
+}
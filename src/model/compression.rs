@@ -0,0 +1,106 @@
+use std::fmt::{Display, Formatter};
+use std::fs::File;
+use std::io;
+use std::io::{BufRead, BufReader, Read, Seek};
+use std::path::Path;
+
+use flate2::read::MultiGzDecoder;
+use ruzstd::decoding::StreamingDecoder;
+
+use crate::model::Error;
+
+const MAGIC_GZIP : [u8; 2] = [0x1F, 0x8B];
+const MAGIC_ZSTD : [u8; 4] = [0x28, 0xB5, 0x2F, 0xFD];
+const MAGIC_XZ : [u8; 6] = [0xFD, 0x37, 0x7A, 0x58, 0x5A, 0x00];
+
+/// The size of the reads from the compressed file, which is also how often progress is reported.
+const READ_BUFFER_SIZE : usize = 1 << 20;
+
+/// The compression formats a capture file can be archived in.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Compression {
+    Gzip,
+    Zstd,
+    Xz,
+}
+
+impl Compression {
+    /// Determines the compression format from the first bytes of a file.
+    pub fn detect(magic: &[u8]) -> Option<Compression> {
+        if magic.starts_with(&MAGIC_GZIP) {
+            Some(Compression::Gzip)
+        } else if magic.starts_with(&MAGIC_ZSTD) {
+            Some(Compression::Zstd)
+        } else if magic.starts_with(&MAGIC_XZ) {
+            Some(Compression::Xz)
+        } else { None }
+    }
+
+    /// Decompresses the file into an anonymous temporary file in `temp_dir`, or in the system's temporary
+    /// directory, which is removed once it is closed. The capture is decoded while it is read, so it need not
+    /// fit in memory, but the temporary file takes as much disk space as the uncompressed capture.
+    /// Playback seeks, loops and indexes the records in place, which needs the whole capture at hand,
+    /// so it is decompressed up front rather than while playing.
+    /// `progress` is called with the number of compressed bytes read so far, and the size of the file.
+    pub fn decompress(&self, mut file: File, temp_dir: Option<&Path>, progress: impl FnMut(u64, u64)) -> Result<File, Error> {
+        let decompress_error = |source| Error::Decompress { compression: *self, source };
+        file.rewind().map_err(Error::ReadFile)?;
+        let total = file.metadata().map_err(Error::ReadFile)?.len();
+        let mut input = BufReader::with_capacity(READ_BUFFER_SIZE, ProgressReader { inner: file, read: 0, total, progress });
+        let mut output = match temp_dir {
+            Some(dir) => {
+                tempfile::tempfile_in(dir).map_err(|err| decompress_error(io::Error::new(err.kind(),
+                    format!("cannot create a temporary file in {}: {err}", dir.display()))))?
+            }
+            None => { tempfile::tempfile().map_err(decompress_error)? }
+        };
+
+        match self {
+            Compression::Gzip => {
+                io::copy(&mut MultiGzDecoder::new(input), &mut output).map_err(decompress_error)?;
+            }
+            Compression::Zstd => {
+                // a zstd file may consist of multiple frames, e.g. when compressed in parallel
+                while !input.fill_buf().map_err(decompress_error)?.is_empty() {
+                    let mut decoder = StreamingDecoder::new(&mut input)
+                        .map_err(|err| decompress_error(io::Error::new(io::ErrorKind::InvalidData, err)))?;
+                    io::copy(&mut decoder, &mut output).map_err(decompress_error)?;
+                }
+            }
+            Compression::Xz => {
+                lzma_rs::xz_decompress(&mut input, &mut output)
+                    .map_err(|err| decompress_error(io::Error::new(io::ErrorKind::InvalidData, err)))?;
+            }
+        }
+
+        output.rewind().map_err(decompress_error)?;
+        Ok(output)
+    }
+}
+
+/// Reports the number of bytes read from the inner reader after every read.
+struct ProgressReader<R, F> {
+    inner: R,
+    read: u64,
+    total: u64,
+    progress: F,
+}
+
+impl<R: Read, F: FnMut(u64, u64)> Read for ProgressReader<R, F> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let length = self.inner.read(buf)?;
+        self.read += length as u64;
+        (self.progress)(self.read, self.total);
+        Ok(length)
+    }
+}
+
+impl Display for Compression {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Compression::Gzip => { write!(f, "gzip") }
+            Compression::Zstd => { write!(f, "zstd") }
+            Compression::Xz => { write!(f, "xz") }
+        }
+    }
+}
@@ -0,0 +1,203 @@
+use std::fmt::{Display, Formatter};
+use std::io;
+use std::net::SocketAddr;
+
+use crate::constants::{ERROR_COMMAND_CHANNEL, ERROR_CORRUPT_FILE, ERROR_CREATE_PLAYER, ERROR_DECOMPRESS_FILE, ERROR_INIT_PLAYER, ERROR_PARSE_FILE, ERROR_READ_FILE, ERROR_REWRITE_RULES, ERROR_SELECT_PACKETS, ERROR_SEND_PACKET, ERROR_UNSUPPORTED_FILE};
+use crate::model::compression::Compression;
+use crate::model::multicast::InterfaceSelector;
+use crate::model::recovery::Damage;
+
+/// All errors of packet-play. Each kind of error exits the application with its own exit code.
+#[derive(Debug)]
+pub enum Error {
+    /// The capture file cannot be opened or mapped into memory.
+    ReadFile(io::Error),
+    Decompress { compression: Compression, source: io::Error },
+    Parse(ParseError),
+    /// The selection of packets to replay is invalid or leaves no packets.
+    SelectPackets(String),
+    Player(PlayerError),
+    Rewrite(RewriteError),
+    /// The user interface stopped sending commands to the player.
+    CommandChannel,
+}
+
+#[derive(Debug)]
+pub enum ParseError {
+    FileTooShort,
+    UnknownMagicNumber(u32),
+    /// The data at `offset` cannot be parsed as the file header or first record of the `format`.
+    Invalid { format: &'static str, offset: usize },
+    NoPackets { format: &'static str },
+    /// The file contains corrupt data, which is only skipped when recovering.
+    Corrupt(Damage),
+}
+
+#[derive(Debug)]
+pub enum RewriteError {
+    Read(io::Error),
+    /// The rule on the (1-based) `line` of the file cannot be parsed.
+    Invalid { line: usize, reason: String },
+}
+
+#[derive(Debug)]
+pub enum PlayerError {
+    /// The builder was not given the named setting.
+    MissingSetting(&'static str),
+    EmptyPlaylist,
+    Bind { address: SocketAddr, source: io::Error },
+    SocketOption { option: &'static str, source: io::Error },
+    /// The network interfaces of the host cannot be listed.
+    ListInterfaces(io::Error),
+    /// No network interface has the selected name or address.
+    UnknownInterface(InterfaceSelector),
+    /// The named multicast interface has no IPv4 address to send IPv4 multicast from.
+    NoInterfaceAddress(String),
+    /// Sending the packet of `record` failed.
+    Send { record: usize, destination: SocketAddr, source: io::Error },
+}
+
+impl Error {
+    pub(crate) fn exit_code(&self) -> i32 {
+        match self {
+            Error::ReadFile(_) => { ERROR_READ_FILE }
+            Error::Decompress { .. } => { ERROR_DECOMPRESS_FILE }
+            Error::Parse(ParseError::FileTooShort) |
+            Error::Parse(ParseError::UnknownMagicNumber(_)) => { ERROR_UNSUPPORTED_FILE }
+            Error::Parse(ParseError::Corrupt(_)) => { ERROR_CORRUPT_FILE }
+            Error::Parse(_) => { ERROR_PARSE_FILE }
+            Error::SelectPackets(_) |
+            Error::Player(PlayerError::EmptyPlaylist) => { ERROR_SELECT_PACKETS }
+            Error::Player(PlayerError::MissingSetting(_)) => { ERROR_CREATE_PLAYER }
+            Error::Player(PlayerError::Bind { .. }) |
+            Error::Player(PlayerError::SocketOption { .. }) |
+            Error::Player(PlayerError::ListInterfaces(_)) |
+            Error::Player(PlayerError::UnknownInterface(_)) |
+            Error::Player(PlayerError::NoInterfaceAddress(_)) => { ERROR_INIT_PLAYER }
+            Error::Player(PlayerError::Send { .. }) => { ERROR_SEND_PACKET }
+            Error::Rewrite(_) => { ERROR_REWRITE_RULES }
+            Error::CommandChannel => { ERROR_COMMAND_CHANNEL }
+        }
+    }
+}
+
+impl ParseError {
+    /// Locates a parse error of the `format` within the `file` it was parsing.
+    pub(crate) fn invalid(format: &'static str, file: &[u8], err: nom::Err<nom::error::Error<&[u8]>>) -> ParseError {
+        let offset = match err {
+            nom::Err::Error(err) | nom::Err::Failure(err) => { file.len() - err.input.len() }
+            nom::Err::Incomplete(_) => { file.len() }
+        };
+        ParseError::Invalid { format, offset }
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::ReadFile(_) => { write!(f, "Cannot read recording") }
+            Error::Decompress { compression, .. } => { write!(f, "Cannot decompress {compression} compressed recording") }
+            Error::Parse(_) => { write!(f, "Cannot parse recording") }
+            Error::SelectPackets(reason) => { write!(f, "Cannot select packets to replay: {reason}") }
+            Error::Player(_) => { write!(f, "Player failed") }
+            Error::Rewrite(_) => { write!(f, "Cannot load rewrite rules") }
+            Error::CommandChannel => { write!(f, "Player lost the connection to the user interface") }
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::ReadFile(err) => { Some(err) }
+            Error::Decompress { source, .. } => { Some(source) }
+            Error::Parse(err) => { Some(err) }
+            Error::Player(err) => { Some(err) }
+            Error::Rewrite(err) => { Some(err) }
+            Error::SelectPackets(_) | Error::CommandChannel => { None }
+        }
+    }
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ParseError::FileTooShort => { write!(f, "file is too short to contain a capture") }
+            ParseError::UnknownMagicNumber(magic) => {
+                write!(f, "unknown magic number {magic:#010X}, the file is not a .pcap or .pcapng file")
+            }
+            ParseError::Invalid { format, offset } => { write!(f, "invalid {format} data at offset {offset}") }
+            ParseError::NoPackets { format } => { write!(f, "{format} file contains no complete packets") }
+            ParseError::Corrupt(damage) => { write!(f, "file contains {damage}, use --recover to skip it") }
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+impl Display for RewriteError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RewriteError::Read(_) => { write!(f, "cannot read file") }
+            RewriteError::Invalid { line, reason } => { write!(f, "line {line}: {reason}") }
+        }
+    }
+}
+
+impl std::error::Error for RewriteError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            RewriteError::Read(err) => { Some(err) }
+            RewriteError::Invalid { .. } => { None }
+        }
+    }
+}
+
+impl Display for PlayerError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PlayerError::MissingSetting(setting) => { write!(f, "no {setting} provided") }
+            PlayerError::EmptyPlaylist => { write!(f, "there are no packets to replay") }
+            PlayerError::Bind { address, .. } => { write!(f, "cannot bind socket to {address}") }
+            PlayerError::SocketOption { option, .. } => { write!(f, "cannot set socket option {option}") }
+            PlayerError::ListInterfaces(_) => { write!(f, "cannot list the network interfaces") }
+            PlayerError::UnknownInterface(InterfaceSelector::Name(name)) => { write!(f, "there is no network interface named {name}") }
+            PlayerError::UnknownInterface(InterfaceSelector::Address(address)) => { write!(f, "there is no network interface with address {address}") }
+            PlayerError::NoInterfaceAddress(name) => { write!(f, "network interface {name} has no IPv4 address to send multicast from") }
+            PlayerError::Send { record, destination, .. } => {
+                write!(f, "cannot send packet {} to {destination}", record + 1)
+            }
+        }
+    }
+}
+
+impl std::error::Error for PlayerError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            PlayerError::Bind { source, .. } |
+            PlayerError::SocketOption { source, .. } |
+            PlayerError::Send { source, .. } |
+            PlayerError::ListInterfaces(source) => { Some(source) }
+            PlayerError::MissingSetting(_) | PlayerError::EmptyPlaylist |
+            PlayerError::UnknownInterface(_) | PlayerError::NoInterfaceAddress(_) => { None }
+        }
+    }
+}
+
+impl From<ParseError> for Error {
+    fn from(err: ParseError) -> Self {
+        Error::Parse(err)
+    }
+}
+
+impl From<RewriteError> for Error {
+    fn from(err: RewriteError) -> Self {
+        Error::Rewrite(err)
+    }
+}
+
+impl From<PlayerError> for Error {
+    fn from(err: PlayerError) -> Self {
+        Error::Player(err)
+    }
+}
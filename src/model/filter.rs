@@ -0,0 +1,414 @@
+use std::fmt::{Display, Formatter};
+use std::net::IpAddr;
+use std::str::FromStr;
+use nom::branch::alt;
+use nom::bytes::complete::{tag, tag_no_case, take_while1};
+use nom::character::complete::{digit1, hex_digit1, multispace0, satisfy};
+use nom::combinator::{all_consuming, map, map_opt, map_res, not, opt, peek, value};
+use nom::IResult;
+use nom::multi::many0;
+use nom::sequence::{delimited, preceded, terminated, tuple};
+use crate::model::link::LinkHeader;
+use crate::model::packet::{ip_protocol_name, NetworkHeader, parse_ip_protocol, TransportHeader};
+
+/// A filter expression in a BPF-like language, selecting the packets to replay, e.g.
+/// `udp dst port 3000 and src host 10.0.0.5`, `vlan 100 and not net 10.1.0.0/16` or `payload[0] = 0xcafe`.
+/// Primitives are combined with `and`, `or` and `not` (or `&&`, `||` and `!`), and grouped with parentheses.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Filter {
+    And(Box<Filter>, Box<Filter>),
+    Or(Box<Filter>, Box<Filter>),
+    Not(Box<Filter>),
+    Primitive(Primitive),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Primitive {
+    Ipv4,
+    Ipv6,
+    /// The transport protocol of an IP packet.
+    Protocol(u8),
+    /// Any VLAN tagged frame, or a frame tagged with the given VLAN id at any level.
+    Vlan(Option<u16>),
+    Host { direction: Direction, address: IpAddr },
+    Net { direction: Direction, address: IpAddr, prefix_length: u8 },
+    /// A transport port, known for UDP only.
+    Port { direction: Direction, port: u16 },
+    /// The transport payload holds the `bytes` at `offset`.
+    Payload { offset: usize, bytes: Vec<u8> },
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub(crate) enum Direction {
+    Source,
+    Destination,
+    Either,
+}
+
+/// The dissected fields of a packet that a filter is matched against.
+pub(crate) struct FilterInput<'a> {
+    pub link: &'a LinkHeader,
+    pub network: &'a NetworkHeader,
+    pub transport: &'a TransportHeader,
+    pub payload: &'a [u8],
+}
+
+impl Filter {
+    pub(crate) fn matches(&self, packet: &FilterInput) -> bool {
+        match self {
+            Filter::And(left, right) => { left.matches(packet) && right.matches(packet) }
+            Filter::Or(left, right) => { left.matches(packet) || right.matches(packet) }
+            Filter::Not(filter) => { !filter.matches(packet) }
+            Filter::Primitive(primitive) => { primitive.matches(packet) }
+        }
+    }
+}
+
+impl Primitive {
+    fn matches(&self, packet: &FilterInput) -> bool {
+        let addresses = match packet.network {
+            NetworkHeader::Ipv4(header) => { Some((IpAddr::V4(header.source), IpAddr::V4(header.destination))) }
+            NetworkHeader::Ipv6(header) => { Some((IpAddr::V6(header.source), IpAddr::V6(header.destination))) }
+            NetworkHeader::Other { .. } => { None }
+        };
+        let ports = match packet.transport {
+            TransportHeader::Udp(header) => { Some((header.source_port, header.destination_port)) }
+            TransportHeader::Other { .. } | TransportHeader::None => { None }
+        };
+
+        match self {
+            Primitive::Ipv4 => { matches!(packet.network, NetworkHeader::Ipv4(_)) }
+            Primitive::Ipv6 => { matches!(packet.network, NetworkHeader::Ipv6(_)) }
+            Primitive::Protocol(protocol) => { packet.network.protocol() == Some(*protocol) }
+            Primitive::Vlan(None) => { !packet.link.vlan_ids.is_empty() }
+            Primitive::Vlan(Some(id)) => { packet.link.vlan_ids.contains(id) }
+            Primitive::Host { direction, address } => {
+                addresses.is_some_and(|addresses| direction.matches(addresses, |host| host == *address))
+            }
+            Primitive::Net { direction, address, prefix_length } => {
+                addresses.is_some_and(|addresses| direction.matches(addresses, |host| in_network(host, *address, *prefix_length)))
+            }
+            Primitive::Port { direction, port } => {
+                ports.is_some_and(|ports| direction.matches(ports, |value| value == *port))
+            }
+            Primitive::Payload { offset, bytes } => {
+                // an offset near usize::MAX cannot hold the bytes at all
+                offset.checked_add(bytes.len())
+                    .and_then(|end| packet.payload.get(*offset..end))
+                    .is_some_and(|payload| payload == bytes.as_slice())
+            }
+        }
+    }
+}
+
+impl Direction {
+    fn matches<T: Copy>(&self, (source, destination): (T, T), predicate: impl Fn(T) -> bool) -> bool {
+        match self {
+            Direction::Source => { predicate(source) }
+            Direction::Destination => { predicate(destination) }
+            Direction::Either => { predicate(source) || predicate(destination) }
+        }
+    }
+}
+
+/// True when the `host` lies within the network given by `address` and `prefix_length`.
+/// Addresses of different families never match.
+pub(crate) fn in_network(host: IpAddr, address: IpAddr, prefix_length: u8) -> bool {
+    match (host, address) {
+        (IpAddr::V4(host), IpAddr::V4(address)) => {
+            let mask = u32::MAX.checked_shl(32 - prefix_length as u32).unwrap_or(0);
+            u32::from(host) & mask == u32::from(address) & mask
+        }
+        (IpAddr::V6(host), IpAddr::V6(address)) => {
+            let mask = u128::MAX.checked_shl(128 - prefix_length as u32).unwrap_or(0);
+            u128::from(host) & mask == u128::from(address) & mask
+        }
+        _ => { false }
+    }
+}
+
+impl FromStr for Filter {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match all_consuming(terminated(expression, multispace0))(value) {
+            Ok((_, filter)) => { Ok(filter) }
+            Err(nom::Err::Error(err)) | Err(nom::Err::Failure(err)) => {
+                let offset = value.len() - err.input.len();
+                Err(format!("invalid filter expression at position {}: '{}'", offset + 1, err.input.trim()))
+            }
+            Err(nom::Err::Incomplete(_)) => { Err("incomplete filter expression".to_string()) }
+        }
+    }
+}
+
+impl Display for Filter {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Filter::And(left, right) => { write!(f, "({left} and {right})") }
+            Filter::Or(left, right) => { write!(f, "({left} or {right})") }
+            Filter::Not(filter) => { write!(f, "not {filter}") }
+            Filter::Primitive(primitive) => { write!(f, "{primitive}") }
+        }
+    }
+}
+
+impl Display for Primitive {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Primitive::Ipv4 => { write!(f, "ip") }
+            Primitive::Ipv6 => { write!(f, "ip6") }
+            Primitive::Protocol(protocol) => {
+                match ip_protocol_name(*protocol) {
+                    Some(name) => { write!(f, "{}", name.to_lowercase()) }
+                    None => { write!(f, "proto {protocol}") }
+                }
+            }
+            Primitive::Vlan(None) => { write!(f, "vlan") }
+            Primitive::Vlan(Some(id)) => { write!(f, "vlan {id}") }
+            Primitive::Host { direction, address } => { write!(f, "{direction}host {address}") }
+            Primitive::Net { direction, address, prefix_length } => { write!(f, "{direction}net {address}/{prefix_length}") }
+            Primitive::Port { direction, port } => { write!(f, "{direction}port {port}") }
+            Primitive::Payload { offset, bytes } => {
+                write!(f, "payload[{offset}] = 0x")?;
+                bytes.iter().try_for_each(|byte| write!(f, "{byte:02x}"))
+            }
+        }
+    }
+}
+
+impl Display for Direction {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Direction::Source => { write!(f, "src ") }
+            Direction::Destination => { write!(f, "dst ") }
+            Direction::Either => { Ok(()) }
+        }
+    }
+}
+
+fn expression(input: &str) -> IResult<&str, Filter> {
+    let (input, first) = conjunction(input)?;
+    let (input, rest) = many0(preceded(alt((keyword("or"), symbol("||"))), conjunction))(input)?;
+    Ok((input, rest.into_iter().fold(first, |left, right| Filter::Or(Box::new(left), Box::new(right)))))
+}
+
+fn conjunction(input: &str) -> IResult<&str, Filter> {
+    let (input, first) = negation(input)?;
+    let (input, rest) = many0(preceded(alt((keyword("and"), symbol("&&"))), negation))(input)?;
+    Ok((input, rest.into_iter().fold(first, |left, right| Filter::And(Box::new(left), Box::new(right)))))
+}
+
+fn negation(input: &str) -> IResult<&str, Filter> {
+    alt((
+        map(preceded(alt((keyword("not"), symbol("!"))), negation), |filter| Filter::Not(Box::new(filter))),
+        delimited(symbol("("), expression, symbol(")")),
+        qualified_primitive,
+    ))(input)
+}
+
+/// A primitive with an optional protocol qualifier, e.g. `udp dst port 3000`, which matches like `udp and dst port 3000`.
+fn qualified_primitive(input: &str) -> IResult<&str, Filter> {
+    let (input, qualifier) = opt(protocol)(input)?;
+    let (input, primitive) = match qualifier {
+        Some(_) => { opt(primitive)(input)? }
+        None => { map(primitive, Some)(input)? }
+    };
+    let filter = match (qualifier, primitive) {
+        (Some(qualifier), Some(primitive)) => {
+            Filter::And(Box::new(Filter::Primitive(qualifier)), Box::new(Filter::Primitive(primitive)))
+        }
+        (Some(primitive), None) | (None, Some(primitive)) => { Filter::Primitive(primitive) }
+        (None, None) => { unreachable!("a primitive is required without a qualifier") }
+    };
+    Ok((input, filter))
+}
+
+fn protocol(input: &str) -> IResult<&str, Primitive> {
+    alt((
+        value(Primitive::Ipv6, keyword("ip6")),
+        value(Primitive::Ipv4, keyword("ip")),
+        map(preceded(keyword("proto"), map_res(word, parse_ip_protocol)), Primitive::Protocol),
+        // a bare protocol name, as opposed to the keywords of other primitives
+        map_res(word, |name| {
+            if name.chars().all(|c| c.is_ascii_digit()) {
+                Err("a protocol number needs the proto keyword".to_string())
+            } else { parse_ip_protocol(name).map(Primitive::Protocol) }
+        }),
+    ))(input)
+}
+
+fn primitive(input: &str) -> IResult<&str, Primitive> {
+    alt((
+        map(preceded(keyword("vlan"), opt(number::<u16>)), Primitive::Vlan),
+        payload,
+        addressed,
+    ))(input)
+}
+
+/// A primitive on the addresses or ports of a packet, with an optional direction.
+fn addressed(input: &str) -> IResult<&str, Primitive> {
+    let (input, direction) = opt(alt((
+        value(Direction::Source, keyword("src")),
+        value(Direction::Destination, keyword("dst")),
+    )))(input)?;
+    let direction = direction.unwrap_or(Direction::Either);
+
+    alt((
+        map(preceded(keyword("host"), address), move |address| Primitive::Host { direction, address }),
+        map_opt(preceded(keyword("net"), tuple((address, preceded(symbol("/"), number::<u8>)))),
+            move |(address, prefix_length)| {
+                let max_prefix_length = if address.is_ipv4() { 32 } else { 128 };
+                (prefix_length <= max_prefix_length).then_some(Primitive::Net { direction, address, prefix_length })
+            }),
+        map(preceded(keyword("port"), number::<u16>), move |port| Primitive::Port { direction, port }),
+    ))(input)
+}
+
+fn payload(input: &str) -> IResult<&str, Primitive> {
+    let (input, offset) = preceded(keyword("payload"), delimited(symbol("["), number::<usize>, symbol("]")))(input)?;
+    let (input, bytes) = preceded(tuple((symbol("="), symbol("0x"))), map_opt(hex_digit1, hex_bytes))(input)?;
+    Ok((input, Primitive::Payload { offset, bytes }))
+}
+
+fn hex_bytes(digits: &str) -> Option<Vec<u8>> {
+    if !digits.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..digits.len()).step_by(2)
+        .map(|index| u8::from_str_radix(&digits[index..index + 2], 16).ok())
+        .collect()
+}
+
+fn address(input: &str) -> IResult<&str, IpAddr> {
+    preceded(multispace0, map_res(take_while1(|c: char| c.is_ascii_hexdigit() || c == '.' || c == ':'), IpAddr::from_str))(input)
+}
+
+fn number<T: FromStr>(input: &str) -> IResult<&str, T> {
+    preceded(multispace0, map_res(terminated(digit1, not(peek(satisfy(is_word_char)))), T::from_str))(input)
+}
+
+fn word(input: &str) -> IResult<&str, &str> {
+    preceded(multispace0, take_while1(is_word_char))(input)
+}
+
+/// A keyword, which must not be directly followed by other word characters.
+fn keyword<'a>(keyword: &'static str) -> impl FnMut(&'a str) -> IResult<&'a str, &'a str> {
+    preceded(multispace0, terminated(tag_no_case(keyword), not(peek(satisfy(is_word_char)))))
+}
+
+fn symbol<'a>(symbol: &'static str) -> impl FnMut(&'a str) -> IResult<&'a str, &'a str> {
+    preceded(multispace0, tag(symbol))
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::model::link::LinkType;
+    use crate::model::packet::DissectedPacket;
+    use super::*;
+
+    fn filter(expression: &str) -> Filter {
+        expression.parse().unwrap_or_else(|err| panic!("{expression}: {err}"))
+    }
+
+    fn primitive(primitive: Primitive) -> Filter {
+        Filter::Primitive(primitive)
+    }
+
+    /// An Ethernet frame with a UDP over IPv4 packet from 10.0.0.1:1000 to 10.1.2.3:3000, optionally VLAN tagged.
+    fn udp_frame(vlan: Option<u16>, payload: &[u8]) -> Vec<u8> {
+        let mut frame = vec![0xFF; 6];
+        frame.extend([0x00; 6]);
+        if let Some(id) = vlan {
+            frame.extend([0x81, 0x00]);
+            frame.extend(id.to_be_bytes());
+        }
+        frame.extend([0x08, 0x00]);
+        let udp_length = 8 + payload.len() as u16;
+        frame.extend([0x45, 0x00]);
+        frame.extend((20 + udp_length).to_be_bytes());
+        frame.extend([0x00, 0x01, 0x00, 0x00, 64, 17, 0x00, 0x00, 10, 0, 0, 1, 10, 1, 2, 3]);
+        frame.extend(1000u16.to_be_bytes());
+        frame.extend(3000u16.to_be_bytes());
+        frame.extend(udp_length.to_be_bytes());
+        frame.extend([0x00, 0x00]);
+        frame.extend(payload);
+        frame
+    }
+
+    fn matches(expression: &str, frame: &[u8]) -> bool {
+        let packet = DissectedPacket::dissect(LinkType::Ethernet, frame, 0).expect("the frame dissects");
+        filter(expression).matches(&FilterInput {
+            link: &packet.link,
+            network: &packet.network,
+            transport: &packet.transport,
+            payload: &frame[packet.payload.clone()],
+        })
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or_and_not_tightest() {
+        let udp = || Box::new(primitive(Primitive::Protocol(17)));
+        assert_eq!(filter("ip or udp and vlan"), Filter::Or(
+            Box::new(primitive(Primitive::Ipv4)),
+            Box::new(Filter::And(udp(), Box::new(primitive(Primitive::Vlan(None)))))));
+        assert_eq!(filter("not udp && ip"), Filter::And(Box::new(Filter::Not(udp())), Box::new(primitive(Primitive::Ipv4))));
+        assert_eq!(filter("!(ip || udp)"), Filter::Not(Box::new(Filter::Or(Box::new(primitive(Primitive::Ipv4)), udp()))));
+    }
+
+    #[test]
+    fn a_protocol_qualifier_expands_to_a_conjunction() {
+        assert_eq!(filter("udp dst port 3000"), Filter::And(
+            Box::new(primitive(Primitive::Protocol(17))),
+            Box::new(primitive(Primitive::Port { direction: Direction::Destination, port: 3000 }))));
+        assert_eq!(filter("udp dst port 3000"), filter("udp and dst port 3000"));
+        assert_eq!(filter("proto 17"), primitive(Primitive::Protocol(17)));
+        assert_eq!(filter("ip6"), primitive(Primitive::Ipv6));
+    }
+
+    #[test]
+    fn net_prefix_lengths_are_bounded_by_the_address_family() {
+        assert!("net 10.0.0.0/32".parse::<Filter>().is_ok());
+        assert!("net 10.0.0.0/33".parse::<Filter>().is_err());
+        assert!("net fd00::/128".parse::<Filter>().is_ok());
+        assert!("net fd00::/129".parse::<Filter>().is_err());
+        assert!("net 10.0.0.0".parse::<Filter>().is_err());
+    }
+
+    #[test]
+    fn errors_point_at_the_position_parsing_stopped() {
+        assert_eq!("udp and".parse::<Filter>(), Err("invalid filter expression at position 5: 'and'".to_string()));
+        assert_eq!("port 70000".parse::<Filter>(), Err("invalid filter expression at position 6: '70000'".to_string()));
+        assert_eq!("17".parse::<Filter>(), Err("invalid filter expression at position 1: '17'".to_string()));
+        assert_eq!("payload[0] = 0xabc".parse::<Filter>(), Err("invalid filter expression at position 1: 'payload[0] = 0xabc'".to_string()));
+    }
+
+    #[test]
+    fn matches_addresses_ports_and_vlans() {
+        let frame = udp_frame(None, b"data");
+        assert!(matches("udp dst port 3000 and src host 10.0.0.1", &frame));
+        assert!(matches("port 1000", &frame));
+        assert!(!matches("src port 3000", &frame));
+        assert!(matches("dst net 10.1.0.0/16", &frame));
+        assert!(!matches("src net 10.1.0.0/16", &frame));
+        assert!(matches("net 0.0.0.0/0", &frame));
+        assert!(!matches("ip6 or vlan", &frame));
+
+        let tagged = udp_frame(Some(100), b"data");
+        assert!(matches("vlan 100 and udp", &tagged));
+        assert!(!matches("vlan 200", &tagged));
+    }
+
+    #[test]
+    fn matches_payload_bytes_within_the_payload() {
+        let frame = udp_frame(None, &[0xCA, 0xFE, 0xBA, 0xBE]);
+        assert!(matches("payload[0] = 0xcafe", &frame));
+        assert!(matches("payload[2] = 0xBABE", &frame));
+        assert!(!matches("payload[3] = 0xbeef", &frame));
+        assert!(!matches("payload[18446744073709551615] = 0x01", &frame));
+    }
+}
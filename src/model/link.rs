@@ -0,0 +1,166 @@
+use nom::bytes::complete::take;
+use nom::IResult;
+use nom::number::complete::{be_u16, be_u32, be_u8, le_u32};
+
+pub(crate) const ETHER_TYPE_IPV4 : u16 = 0x0800;
+pub(crate) const ETHER_TYPE_IPV6 : u16 = 0x86DD;
+const ETHER_TYPE_ARP : u16 = 0x0806;
+const ETHER_TYPE_LLDP : u16 = 0x88CC;
+const ETHER_TYPE_VLAN : u16 = 0x8100;
+const ETHER_TYPE_QINQ : u16 = 0x88A8;
+const ETHER_TYPE_QINQ_LEGACY : u16 = 0x9100;
+
+const ETHERNET_ADDRESSES_LENGTH : usize = 12;
+const LINUX_SLL_HEADER_LENGTH : usize = 16;
+const LINUX_SLL2_HEADER_LENGTH : usize = 20;
+
+// Address families as used in the BSD loopback header; the IPv6 value differs per OS.
+const AF_INET : u32 = 2;
+const AF_INET6_LINUX : u32 = 10;
+const AF_INET6_BSD : u32 = 24;
+const AF_INET6_FREEBSD : u32 = 28;
+const AF_INET6_DARWIN : u32 = 30;
+
+/// The link-layer header types (see tcpdump.org/linktypes.html) we can decode.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum LinkType {
+    Null,       // 0 - BSD loopback, address family in the byte order of the capturing host
+    Ethernet,   // 1 - IEEE 802.3 Ethernet, optionally with 802.1Q/802.1ad VLAN tags
+    Raw,        // 101 (and 12, 14 on some platforms) - raw IPv4 or IPv6
+    Loop,       // 108 - OpenBSD loopback, address family in network byte order
+    LinuxSll,   // 113 - Linux cooked capture v1 (`tcpdump -i any`)
+    LinuxSll2,  // 276 - Linux cooked capture v2
+    Ipv4,       // 228 - raw IPv4
+    Ipv6,       // 229 - raw IPv6
+    Unsupported(u32),
+}
+
+/// The upper bits of the pcap link type field carry the FCS information, which is masked off here.
+impl From<u32> for LinkType {
+    fn from(value: u32) -> Self {
+        match value & 0x0000FFFF {
+            0 => LinkType::Null,
+            1 => LinkType::Ethernet,
+            12 | 14 | 101 => LinkType::Raw,
+            108 => LinkType::Loop,
+            113 => LinkType::LinuxSll,
+            228 => LinkType::Ipv4,
+            229 => LinkType::Ipv6,
+            276 => LinkType::LinuxSll2,
+            other => LinkType::Unsupported(other),
+        }
+    }
+}
+
+/// The decoded link-layer header of a frame.
+/// `ether_type` identifies the network protocol that follows, normalised to its EtherType value.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LinkHeader {
+    pub link_type: LinkType,
+    pub source_mac: Option<[u8; 6]>,
+    pub destination_mac: Option<[u8; 6]>,
+    pub ether_type: u16,
+    pub vlan_ids: Vec<u16>,
+    pub header_length: usize,
+}
+
+/// Decodes the link-layer header for the given link type, returning the network-layer data as remaining input.
+pub fn link_header(link_type: LinkType) -> impl Fn(&[u8]) -> IResult<&[u8], LinkHeader> {
+    move |input| {
+        let mut addresses = None;
+        let (rest, (ether_type, vlan_ids)) = match link_type {
+            LinkType::Ethernet => {
+                let (rest, (destination, source)) = ethernet_addresses(input)?;
+                addresses = Some((destination, source));
+                ethernet_type(rest)?
+            }
+            LinkType::Null => {
+                let (rest, family) = le_u32(input)?;
+                let family = if family > 0xFFFF { family.swap_bytes() } else { family };
+                (rest, (ether_type_from_family(family, input)?, vec![]))
+            }
+            LinkType::Loop => {
+                let (rest, family) = be_u32(input)?;
+                (rest, (ether_type_from_family(family, input)?, vec![]))
+            }
+            LinkType::Raw => { (input, (ether_type_from_ip_version(input)?, vec![])) }
+            LinkType::Ipv4 => { (input, (ETHER_TYPE_IPV4, vec![])) }
+            LinkType::Ipv6 => { (input, (ETHER_TYPE_IPV6, vec![])) }
+            LinkType::LinuxSll => {
+                let (rest, sll_header) = take(LINUX_SLL_HEADER_LENGTH)(input)?;
+                let (_, ether_type) = be_u16(&sll_header[14..])?;
+                (rest, (ether_type, vec![]))
+            }
+            LinkType::LinuxSll2 => {
+                let (rest, sll2_header) = take(LINUX_SLL2_HEADER_LENGTH)(input)?;
+                let (_, ether_type) = be_u16(sll2_header)?;
+                (rest, (ether_type, vec![]))
+            }
+            LinkType::Unsupported(_) => {
+                return Err(nom::Err::Failure(nom::error::Error::new(input, nom::error::ErrorKind::Switch)));
+            }
+        };
+
+        Ok((rest, LinkHeader {
+            link_type,
+            source_mac: addresses.map(|(_, source)| source),
+            destination_mac: addresses.map(|(destination, _)| destination),
+            ether_type,
+            vlan_ids,
+            header_length: input.len() - rest.len(),
+        }))
+    }
+}
+
+pub(crate) fn ether_type_name(ether_type: u16) -> Option<&'static str> {
+    match ether_type {
+        ETHER_TYPE_IPV4 => Some("IPv4"),
+        ETHER_TYPE_IPV6 => Some("IPv6"),
+        ETHER_TYPE_ARP => Some("ARP"),
+        ETHER_TYPE_LLDP => Some("LLDP"),
+        _ => None,
+    }
+}
+
+fn ethernet_addresses(input: &[u8]) -> IResult<&[u8], ([u8; 6], [u8; 6])> {
+    let (input, addresses) = take(ETHERNET_ADDRESSES_LENGTH)(input)?;
+    let mut destination = [0u8; 6];
+    let mut source = [0u8; 6];
+    destination.copy_from_slice(&addresses[..6]);
+    source.copy_from_slice(&addresses[6..]);
+    Ok((input, (destination, source)))
+}
+
+/// The EtherType of an Ethernet II header, skipping any number of stacked VLAN tags.
+fn ethernet_type(input: &[u8]) -> IResult<&[u8], (u16, Vec<u16>)> {
+    let mut input = input;
+    let mut vlan_ids = vec![];
+    loop {
+        let (rest, ether_type) = be_u16(input)?;
+        match ether_type {
+            ETHER_TYPE_VLAN | ETHER_TYPE_QINQ | ETHER_TYPE_QINQ_LEGACY => {
+                let (rest, tag_control) = be_u16(rest)?;
+                vlan_ids.push(tag_control & 0x0FFF);
+                input = rest;
+            }
+            _ => { return Ok((rest, (ether_type, vlan_ids))); }
+        }
+    }
+}
+
+fn ether_type_from_family(family: u32, input: &[u8]) -> Result<u16, nom::Err<nom::error::Error<&[u8]>>> {
+    match family {
+        AF_INET => Ok(ETHER_TYPE_IPV4),
+        AF_INET6_LINUX | AF_INET6_BSD | AF_INET6_FREEBSD | AF_INET6_DARWIN => Ok(ETHER_TYPE_IPV6),
+        _ => Err(nom::Err::Error(nom::error::Error::new(input, nom::error::ErrorKind::Switch))),
+    }
+}
+
+fn ether_type_from_ip_version(input: &[u8]) -> Result<u16, nom::Err<nom::error::Error<&[u8]>>> {
+    let (_, version_ihl) = be_u8(input)?;
+    match version_ihl >> 4 {
+        4 => Ok(ETHER_TYPE_IPV4),
+        6 => Ok(ETHER_TYPE_IPV6),
+        _ => Err(nom::Err::Error(nom::error::Error::new(input, nom::error::ErrorKind::Switch))),
+    }
+}
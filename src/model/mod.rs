@@ -1,69 +1,450 @@
+use std::fmt::{Display, Formatter};
+use std::fs::File;
+use std::io::Read;
+use std::net::{IpAddr, SocketAddr};
+use std::path::Path;
+use std::str::FromStr;
 use std::time::Duration;
-use crate::model::pcap::Pcap;
-use crate::model::pcapng::PcapNG;
+use log::info;
+use memmap2::{Advice, Mmap};
+use crate::clock::TimingStatistics;
+use crate::model::compression::Compression;
+use crate::model::packet::DissectedPacket;
+pub(crate) use crate::model::error::{Error, ParseError, PlayerError};
+use crate::model::pcap::{Pcap, PcapMagicNumber};
+use crate::model::pcapng::{BLOCK_TYPE_SHB, PcapNG};
+use crate::model::playlist::PlaylistStatistics;
+use crate::model::recovery::Damage;
 use crate::player::PlayerState;
 
+pub(crate) mod compression;
+pub(crate) mod error;
+pub(crate) mod filter;
+pub(crate) mod link;
+pub(crate) mod multicast;
+pub(crate) mod packet;
 pub(crate) mod pcap;
 pub(crate) mod pcapng;
+pub(crate) mod playlist;
+pub(crate) mod reassembly;
+pub(crate) mod recovery;
+pub(crate) mod rewrite;
 
-pub(crate) const ETHERNET_HEADER_LENGTH : u16 = 13;
-pub(crate) const IP_HEADER_LENGTH : u16 = 20;
-pub(crate) const UDP_HEADER_LENGTH : u16 = 8;
-
-#[derive(Clone, Debug)]
-pub enum Error {
-    ParsePcapError,
-    ParsePcapNgError,
-    PlayerInitError,
-    FileTypeNotSupported(String),
-    CommandChannelError,
-}
-
+#[derive(Debug)]
+#[allow(clippy::upper_case_acronyms)]
 pub(crate) enum Recording {
     PCAP(Pcap),
     PCAPNG(PcapNG),
 }
 
+impl Recording {
+    pub(crate) fn packet_count(&self) -> usize {
+        match self {
+            Recording::PCAP(pcap) => { pcap.packets.len() }
+            Recording::PCAPNG(pcapng) => { pcapng.packets.len() }
+        }
+    }
+
+    pub(crate) fn packet_timestamp(&self, index: usize) -> Duration {
+        match self {
+            Recording::PCAP(pcap) => { pcap.packets[index].timestamp(&pcap.header.magic_number) }
+            Recording::PCAPNG(pcapng) => { pcapng.packets[index].timestamp }
+        }
+    }
+
+    /// Dissects the headers of a packet. This is done on every call, as dissections are not stored.
+    pub(crate) fn packet_dissection(&self, index: usize) -> Option<DissectedPacket> {
+        match self {
+            Recording::PCAP(pcap) => { pcap.dissect(index) }
+            Recording::PCAPNG(pcapng) => { pcapng.dissect(index) }
+        }
+    }
+
+    pub(crate) fn packet_data(&self, index: usize) -> &[u8] {
+        match self {
+            Recording::PCAP(pcap) => { pcap.packet_data(index) }
+            Recording::PCAPNG(pcapng) => { pcapng.packet_data(index) }
+        }
+    }
+
+    /// The number of bytes of a packet that were cut off by the snap length while capturing.
+    pub(crate) fn packet_truncated_length(&self, index: usize) -> usize {
+        match self {
+            Recording::PCAP(pcap) => { pcap.truncated_length(index) }
+            Recording::PCAPNG(pcapng) => { pcapng.truncated_length(index) }
+        }
+    }
+
+    /// The damaged regions that were skipped while parsing the file.
+    pub(crate) fn damage(&self) -> &[Damage] {
+        match self {
+            Recording::PCAP(pcap) => { &pcap.damage }
+            Recording::PCAPNG(pcapng) => { &pcapng.damage }
+        }
+    }
+
+    /// Whether the timestamps of the records never decrease in file order.
+    pub(crate) fn in_capture_order(&self) -> bool {
+        match self {
+            Recording::PCAP(pcap) => { pcap.in_capture_order }
+            Recording::PCAPNG(pcapng) => { pcapng.in_capture_order }
+        }
+    }
+
+    /// The number of records before the first record for which `is_after` holds. The records are binary searched
+    /// when they are in capture order, and scanned in file order otherwise.
+    pub(crate) fn records_before(&self, is_after: impl Fn(Duration) -> bool) -> usize {
+        if !self.in_capture_order() {
+            return (0..self.packet_count())
+                .find(|index| is_after(self.packet_timestamp(*index)))
+                .unwrap_or(self.packet_count());
+        }
+        let (mut low, mut high) = (0, self.packet_count());
+        while low < high {
+            let middle = low + (high - low) / 2;
+            if is_after(self.packet_timestamp(middle)) {
+                high = middle;
+            } else {
+                low = middle + 1;
+            }
+        }
+        low
+    }
+
+    /// Restricts replay to the packets captured on the selected interfaces. The other packets are kept
+    /// in the recording, so that packet numbers keep matching the frame numbers of the capture.
+    /// A .pcap file has a single interface, so there is nothing to select from.
+    pub(crate) fn select_interfaces(&mut self, selection: &[String]) -> Result<(), Error> {
+        match self {
+            Recording::PCAP(_) => {
+                Err(Error::SelectPackets("selecting interfaces is only supported for .pcapng files".to_string()))
+            }
+            Recording::PCAPNG(pcapng) => {
+                pcapng.select_interfaces(selection);
+                if !(0..pcapng.packets.len()).any(|index| pcapng.is_selected(index)) {
+                    Err(Error::SelectPackets("no packets are captured on the selected interfaces".to_string()))
+                } else { Ok(()) }
+            }
+        }
+    }
+
+    /// Whether the packet is captured on one of the selected interfaces.
+    pub(crate) fn is_selected(&self, index: usize) -> bool {
+        match self {
+            Recording::PCAP(_) => { true }
+            Recording::PCAPNG(pcapng) => { pcapng.is_selected(index) }
+        }
+    }
+}
+
+/// The number of bytes needed to recognise any of the supported compression and capture formats.
+const MAGIC_LENGTH : u64 = 6;
+
+impl Recording {
+    /// Determines the file format from the first bytes. A compressed file is first decompressed into a temporary
+    /// file in `temp_dir` (see `Compression::decompress`), reporting the compressed bytes read to `progress`;
+    /// otherwise the first four bytes hold either the pcapng Section Header Block type or one of the pcap magic numbers.
+    pub(crate) fn open(mut file: File, temp_dir: Option<&Path>, progress: &mut dyn FnMut(u64, u64)) -> Result<Self, Error> {
+        let mut magic = vec![];
+        (&mut file).take(MAGIC_LENGTH).read_to_end(&mut magic).map_err(Error::ReadFile)?;
+
+        if let Some(compression) = Compression::detect(&magic) {
+            info!("Decompressing {compression} compressed recording into a temporary file in {}.",
+                temp_dir.map_or_else(std::env::temp_dir, Path::to_path_buf).display());
+            let decompressed = compression.decompress(file, temp_dir, progress)?;
+            return Recording::open(decompressed, temp_dir, &mut |_, _| {});
+        }
+
+        let magic: [u8; 4] = magic.get(..4)
+            .and_then(|magic| magic.try_into().ok())
+            .ok_or(ParseError::FileTooShort)?;
+
+        if u32::from_le_bytes(magic) == BLOCK_TYPE_SHB {
+            Ok(Recording::PCAPNG(PcapNG::try_from(file)?))
+        } else {
+            let (magic_number, endianness) = PcapMagicNumber::detect(&magic)?;
+            Ok(Recording::PCAP(Pcap::parse(file, magic_number, endianness)?))
+        }
+    }
+}
+
+/// Memory-maps a capture file, so that even captures larger than the available memory can be replayed.
+/// Pages are read in on first access, and can be dropped again by the OS once played.
+pub(crate) fn map_file(file: &File) -> Result<Mmap, Error> {
+    // SAFETY: the mapping is read-only; the capture file must not be truncated while it is replayed.
+    let data = unsafe { Mmap::map(file) }
+        .map_err(Error::ReadFile)?;
+    let _ = data.advise(Advice::Sequential);
+    Ok(data)
+}
+
 #[derive(Copy, Clone, PartialEq)]
 pub(crate) enum Command {
     Play,
     Pause,
     Rewind,
+    Seek(SeekTarget),
+    Speed(Speed),
+    /// Sends the next packets one by one while paused, regardless of their timing.
+    Step(usize),
     Quit,
     Unspecified,
     // SyncTerm, // indicates the player that the CLI is ready drawing
 }
 
-impl Command {
-    pub fn as_vec() -> Vec<&'static str> {
-        vec![
-            "Play",
-            "Pause",
-            "Rewind",
-            "Quit",
-            "", // empty line for the progress bar
-        ]
+/// Where to send the replayed packets. A field that is `None` is taken from the destination
+/// of each packet as captured, so that packets to several hosts, groups or ports keep going there.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub(crate) struct Destination {
+    pub address: Option<IpAddr>,
+    pub port: Option<u16>,
+}
+
+/// The wildcard for a destination field or source port that is taken from the captured packets.
+const ORIGINAL_DESTINATION : &str = "*";
+
+impl Destination {
+    /// The destination to send a packet to, given its captured destination address and port.
+    /// Returns `None` when the port is taken from the packet, but not known for it.
+    pub(crate) fn resolve(&self, address: IpAddr, port: Option<u16>) -> Option<SocketAddr> {
+        Some(SocketAddr::new(self.address.unwrap_or(address), self.port.or(port)?))
+    }
+}
+
+impl From<SocketAddr> for Destination {
+    fn from(address: SocketAddr) -> Self {
+        Self {
+            address: Some(address.ip()),
+            port: Some(address.port()),
+        }
+    }
+}
+
+/// Parses a socket address in which the address, the port or both may be `*` to keep the captured value,
+/// e.g. `*`, `*:3000`, `239.1.1.1:*` or `[ff05::1]:*`.
+impl FromStr for Destination {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        if let Ok(address) = value.parse::<SocketAddr>() {
+            return Ok(Destination::from(address));
+        }
+        if value == ORIGINAL_DESTINATION {
+            return Ok(Destination { address: None, port: None });
+        }
+        let invalid = || format!("invalid destination '{value}', expected <address>:<port>, where either may be '{ORIGINAL_DESTINATION}'");
+        let (address, port) = value.rsplit_once(':').ok_or_else(invalid)?;
+        // an IPv6 address is bracketed, so that `::1` is not taken for the address `::` with port 1
+        let address = match address.strip_prefix('[').and_then(|address| address.strip_suffix(']')) {
+            Some(address) => { address }
+            None if address.contains(':') => { return Err(invalid()); }
+            None => { address }
+        };
+        Ok(Destination {
+            address: if address == ORIGINAL_DESTINATION { None } else { Some(address.parse().map_err(|_| invalid())?) },
+            port: if port == ORIGINAL_DESTINATION { None } else { Some(port.parse().map_err(|_| invalid())?) },
+        })
     }
 }
 
-impl From<usize> for Command {
-    fn from(value: usize) -> Self {
-        match value {
-            0 => { Command::Play }
-            1 => { Command::Pause }
-            2 => { Command::Rewind }
-            3 => { Command::Quit }
-            _ => { Command::Unspecified }
+impl Display for Destination {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self.address {
+            Some(IpAddr::V6(address)) => { write!(f, "[{address}]")? }
+            Some(address) => { write!(f, "{address}")? }
+            None => { write!(f, "{ORIGINAL_DESTINATION}")? }
+        }
+        match self.port {
+            Some(port) => { write!(f, ":{port}") }
+            None => { write!(f, ":{ORIGINAL_DESTINATION}") }
         }
     }
 }
 
-#[derive(Clone)]
+/// The port to send the replayed packets from: a fixed port, or the source port of each packet as captured.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub(crate) enum SourcePort {
+    Fixed(u16),
+    Original,
+}
+
+impl SourcePort {
+    /// The port to send a packet from, given its captured source port. Packets of which the source port
+    /// is not known are sent from an ephemeral port.
+    pub(crate) fn resolve(&self, port: Option<u16>) -> u16 {
+        match self {
+            SourcePort::Fixed(port) => { *port }
+            SourcePort::Original => { port.unwrap_or(0) }
+        }
+    }
+}
+
+/// Parses a port number, or `*` to keep the captured source port.
+impl FromStr for SourcePort {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        if value == ORIGINAL_DESTINATION {
+            return Ok(SourcePort::Original);
+        }
+        value.parse::<u16>()
+            .map(SourcePort::Fixed)
+            .map_err(|_| format!("invalid source port '{value}', expected a port number or '{ORIGINAL_DESTINATION}'"))
+    }
+}
+
+impl Display for SourcePort {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SourcePort::Fixed(port) => { write!(f, "{port}") }
+            SourcePort::Original => { write!(f, "{ORIGINAL_DESTINATION}") }
+        }
+    }
+}
+
+pub(crate) const MIN_SPEED_FACTOR : f64 = 0.1;
+pub(crate) const MAX_SPEED_FACTOR : f64 = 100.0;
+
+/// The playback speed, relative to the recorded timing.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub(crate) enum Speed {
+    /// Scales the time between packets by `1 / factor`.
+    Factor(f64),
+    /// Sends the packets as fast as possible, ignoring their timestamps.
+    Max,
+}
+
+impl Speed {
+    /// The time to wait before sending a packet that was captured `interval` after the previous one.
+    pub(crate) fn scale(&self, interval: Duration) -> Duration {
+        match self {
+            Speed::Factor(factor) => { interval.div_f64(*factor) }
+            Speed::Max => { Duration::ZERO }
+        }
+    }
+}
+
+/// Parses a speed factor between 0.1 and 100 (`2`, `0.5x`), or `max` to play as fast as possible.
+impl FromStr for Speed {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let value = value.trim();
+        if value.eq_ignore_ascii_case("max") {
+            return Ok(Speed::Max);
+        }
+        match value.strip_suffix('x').unwrap_or(value).parse::<f64>() {
+            Ok(factor) if (MIN_SPEED_FACTOR..=MAX_SPEED_FACTOR).contains(&factor) => { Ok(Speed::Factor(factor)) }
+            _ => { Err(format!("invalid speed '{value}', expected a factor from {MIN_SPEED_FACTOR} to {MAX_SPEED_FACTOR} or 'max'")) }
+        }
+    }
+}
+
+impl Display for Speed {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Speed::Factor(factor) => { write!(f, "{factor}x") }
+            Speed::Max => { write!(f, "max") }
+        }
+    }
+}
+
+/// A position in the recording to continue playback from, or to start or end the replayed window at.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub(crate) enum SeekTarget {
+    /// The index of a record in the recording, which is its frame number (as shown by e.g. Wireshark) minus one.
+    Packet(usize),
+    /// A time offset, forward or `backward`. When seeking, the offset is taken from the packet that was played last.
+    Offset { offset: Duration, backward: bool },
+    /// An absolute capture timestamp, as a time since the unix epoch.
+    Timestamp(Duration),
+}
+
+/// Parses a seek target as typed by the user: a packet number (`120` or `#120`, counting from 1),
+/// a relative time offset (`+30s`, `-1m30s`) or an absolute capture timestamp in unix seconds (`@1650000000.5`).
+impl FromStr for SeekTarget {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let value = value.trim();
+        if let Some(offset) = value.strip_prefix('+') {
+            Ok(SeekTarget::Offset { offset: parse_duration(offset)?, backward: false })
+        } else if let Some(offset) = value.strip_prefix('-') {
+            Ok(SeekTarget::Offset { offset: parse_duration(offset)?, backward: true })
+        } else if let Some(timestamp) = value.strip_prefix('@') {
+            parse_timestamp(timestamp)
+                .map(SeekTarget::Timestamp)
+                .ok_or_else(|| format!("invalid capture timestamp '{timestamp}'"))
+        } else {
+            match value.strip_prefix('#').unwrap_or(value).parse::<usize>() {
+                Ok(number) if number > 0 => { Ok(SeekTarget::Packet(number - 1)) }
+                _ => { Err(format!("invalid seek target '{value}', expected a packet number, +/-offset or @timestamp")) }
+            }
+        }
+    }
+}
+
+impl Display for SeekTarget {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SeekTarget::Packet(index) => { write!(f, "#{}", index + 1) }
+            SeekTarget::Offset { offset, backward } => {
+                write!(f, "{}{}s", if *backward { "-" } else { "+" }, offset.as_secs_f64())
+            }
+            SeekTarget::Timestamp(timestamp) => { write!(f, "@{}.{:09}", timestamp.as_secs(), timestamp.subsec_nanos()) }
+        }
+    }
+}
+
+/// Parses unix seconds with up to nanosecond precision, e.g. `1650000000.123456`.
+/// The fraction is parsed as digits rather than as a float, so that it matches the capture timestamps exactly.
+fn parse_timestamp(value: &str) -> Option<Duration> {
+    let (seconds, fraction) = value.split_once('.').unwrap_or((value, ""));
+    if !fraction.chars().all(|c| c.is_ascii_digit()) || fraction.len() > 9 {
+        return None;
+    }
+    let nanos = if fraction.is_empty() { 0 } else { format!("{fraction:0<9}").parse::<u32>().ok()? };
+    Some(Duration::new(seconds.parse::<u64>().ok()?, nanos))
+}
+
+/// Parses a duration made up of one or more numbers with a unit (`h`, `m`, `s` or `ms`), e.g. `1m30s` or `2.5s`.
+/// A number without a unit counts seconds.
+pub(crate) fn parse_duration(value: &str) -> Result<Duration, String> {
+    let invalid = || format!("invalid duration '{value}', expected e.g. 30s, 1m30s or 500ms");
+    let mut rest = value.trim();
+    if rest.is_empty() {
+        return Err(invalid());
+    }
+    let mut duration = Duration::ZERO;
+    while !rest.is_empty() {
+        let number_length = rest.find(|c: char| !c.is_ascii_digit() && c != '.').unwrap_or(rest.len());
+        let number = rest[..number_length].parse::<f64>().map_err(|_| invalid())?;
+        rest = &rest[number_length..];
+        let unit_length = rest.find(|c: char| c.is_ascii_digit() || c == '.').unwrap_or(rest.len());
+        let seconds_per_unit = match &rest[..unit_length] {
+            "h" => { 3600.0 }
+            "m" => { 60.0 }
+            "s" | "" => { 1.0 }
+            "ms" => { 0.001 }
+            _ => { return Err(invalid()); }
+        };
+        rest = &rest[unit_length..];
+        duration += Duration::try_from_secs_f64(number * seconds_per_unit).map_err(|_| invalid())?;
+    }
+    Ok(duration)
+}
+
 pub enum Event {
     Error(Error),
     PlayerReady,
     PlayerStateChanged(StateChange),
     PlayerPositionChanged(PositionChange),
+    PlayerSpeedChanged(Speed),
+    PacketStepped(SteppedPacket),
+    /// Reports how accurately the packets were sent on schedule, when playback finishes or the player quits or fails.
+    TimingReport(TimingStatistics),
+    /// Reports which packets were replayed and why the others were skipped, along with the `TimingReport`.
+    PlaylistReport(PlaylistStatistics),
     QuitCommanded,
 }
 
@@ -74,13 +455,15 @@ impl Event {
         })
     }
 
-    pub(crate) fn position_event(current_pos: usize, max_pos: usize, current_time:Duration, total_time: Duration) -> Self {
+    pub(crate) fn position_event(current_pos: usize, max_pos: usize, current_time:Duration, total_time: Duration, iteration: usize, matched: usize) -> Self {
         // This function increases the positions with +1 to compensate for 0-based vec indexing.
         Event::PlayerPositionChanged(PositionChange{
             position: current_pos+1,
             max_position: max_pos+1,
             time_position: current_time,
             time_total: total_time,
+            iteration: iteration+1,
+            matched,
         })
     }
 
@@ -100,6 +483,18 @@ pub struct PositionChange {
     pub(crate) max_position: usize,
     pub(crate) time_position: Duration,
     pub(crate) time_total: Duration,
+    /// The number of times playback started from the first packet when looping.
+    pub(crate) iteration: usize,
+    /// The number of packets read so far that matched the filter.
+    pub(crate) matched: usize,
+}
+
+/// A packet that was sent by stepping. `index` is the index of its record in the recording.
+#[derive(Copy, Clone)]
+pub struct SteppedPacket {
+    pub(crate) index: usize,
+    pub(crate) timestamp: Duration,
+    pub(crate) length: usize,
 }
 
 impl Default for PositionChange {
@@ -109,43 +504,54 @@ impl Default for PositionChange {
             max_position: 0,
             time_position: Duration::from_secs(0),
             time_total: Duration::from_secs(0),
+            iteration: 1,
+            matched: 0,
         }
     }
 }
 
-// pub struct UdpPacket {
-//     pub header: UdpHeader,
-//     pub data: Vec<u8>,
-// }
-//
-// pub struct UdpHeader {
-//     pub source_port: u16,
-//     pub destination_port: u16,
-//     pub length: u16,
-//     pub checksum: u16,
-// }
-//
-// fn parse_udp_packet(input: &[u8]) -> IResult<&[u8], UdpPacket> {
-//     let (input, header) = udp_header(input)?;
-//     let (input, data) = take(header.length - UDP_HEADER_LENGTH)(input)?;
-//     let data = data.to_vec();
-//
-//     Ok((input, UdpPacket {
-//         header,
-//         data
-//     }))
-// }
-//
-// fn udp_header(input: &[u8]) -> IResult<&[u8], UdpHeader> {
-//     let (input, source_port) = be_u16(input)?;
-//     let (input, destination_port) = be_u16(input)?;
-//     let (input, length) = be_u16(input)?;
-//     let (input, checksum) = be_u16(input)?;
-//
-//     Ok((input, UdpHeader {
-//         source_port,
-//         destination_port,
-//         length,
-//         checksum,
-//     }))
-// }
\ No newline at end of file
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv4Addr, Ipv6Addr};
+    use super::*;
+
+    fn destination(value: &str) -> Destination {
+        value.parse().unwrap_or_else(|err| panic!("{value}: {err}"))
+    }
+
+    #[test]
+    fn parses_destinations_with_captured_fields() {
+        let group = IpAddr::V4(Ipv4Addr::new(239, 1, 1, 1));
+        assert_eq!(destination("239.1.1.1:3000"), Destination { address: Some(group), port: Some(3000) });
+        assert_eq!(destination("*"), Destination { address: None, port: None });
+        assert_eq!(destination("*:*"), Destination { address: None, port: None });
+        assert_eq!(destination("*:3000"), Destination { address: None, port: Some(3000) });
+        assert_eq!(destination("239.1.1.1:*"), Destination { address: Some(group), port: None });
+
+        let ipv6_group = IpAddr::V6(Ipv6Addr::new(0xff05, 0, 0, 0, 0, 0, 0, 1));
+        assert_eq!(destination("[ff05::1]:3000"), Destination { address: Some(ipv6_group), port: Some(3000) });
+        assert_eq!(destination("[ff05::1]:*"), Destination { address: Some(ipv6_group), port: None });
+    }
+
+    #[test]
+    fn rejects_destinations_without_a_port_or_with_an_unbracketed_ipv6_address() {
+        for value in ["239.1.1.1", "::1", "ff05::1:*", "*:70000", "localhost:3000", "239.1.1.1:", ""] {
+            assert!(value.parse::<Destination>().is_err(), "{value}");
+        }
+    }
+
+    #[test]
+    fn displays_destinations_as_they_are_parsed() {
+        for value in ["239.1.1.1:3000", "*:*", "*:3000", "239.1.1.1:*", "[ff05::1]:*"] {
+            assert_eq!(destination(value).to_string(), value);
+        }
+    }
+
+    #[test]
+    fn resolves_captured_fields_from_the_packet() {
+        let captured = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2));
+        assert_eq!(destination("*:3000").resolve(captured, None), Some(SocketAddr::new(captured, 3000)));
+        assert_eq!(destination("*").resolve(captured, Some(4000)), Some(SocketAddr::new(captured, 4000)));
+        assert_eq!(destination("*").resolve(captured, None), None);
+    }
+}
@@ -0,0 +1,82 @@
+use std::fmt::{Display, Formatter};
+use std::net::{IpAddr, Ipv4Addr};
+use std::str::FromStr;
+
+use if_addrs::IfAddr;
+
+use crate::model::PlayerError;
+
+/// Selects the network interface to send multicast packets from, by name (`eth1`) or by one of its addresses.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum InterfaceSelector {
+    Name(String),
+    Address(IpAddr),
+}
+
+/// The interface to send multicast packets from, as the socket options need it:
+/// IPv4 selects the interface by one of its addresses, IPv6 by its index.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct MulticastInterface {
+    pub name: String,
+    pub ipv4: Option<Ipv4Addr>,
+    pub index: Option<u32>,
+}
+
+impl InterfaceSelector {
+    /// Looks up the selected interface among the network interfaces of the host.
+    pub(crate) fn resolve(&self) -> Result<MulticastInterface, PlayerError> {
+        let interfaces = if_addrs::get_if_addrs().map_err(PlayerError::ListInterfaces)?;
+        let name = match self {
+            InterfaceSelector::Name(name) => { interfaces.iter().find(|interface| &interface.name == name) }
+            InterfaceSelector::Address(address) => { interfaces.iter().find(|interface| interface.ip() == *address) }
+        }.map(|interface| interface.name.clone())
+            .ok_or_else(|| PlayerError::UnknownInterface(self.clone()))?;
+
+        // an interface is listed once for each of its addresses
+        let addresses = interfaces.iter().filter(|interface| interface.name == name);
+        let ipv4 = match self {
+            InterfaceSelector::Address(IpAddr::V4(address)) => { Some(*address) }
+            _ => {
+                addresses.clone().find_map(|interface| match &interface.addr {
+                    IfAddr::V4(address) => { Some(address.ip) }
+                    IfAddr::V6(_) => { None }
+                })
+            }
+        };
+        let index = addresses.clone().find_map(|interface| interface.index);
+        Ok(MulticastInterface { name, ipv4, index })
+    }
+}
+
+/// The broadcast addresses of the IPv4 networks of the host's interfaces.
+pub(crate) fn broadcast_addresses() -> Result<Vec<Ipv4Addr>, PlayerError> {
+    let interfaces = if_addrs::get_if_addrs().map_err(PlayerError::ListInterfaces)?;
+    Ok(interfaces.into_iter()
+        .filter_map(|interface| match interface.addr {
+            IfAddr::V4(address) => { address.broadcast }
+            IfAddr::V6(_) => { None }
+        })
+        .collect())
+}
+
+/// Parses an IP address, or otherwise an interface name.
+impl FromStr for InterfaceSelector {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        if value.is_empty() {
+            return Err("expected an interface name or address".to_string());
+        }
+        Ok(value.parse::<IpAddr>()
+            .map_or_else(|_| InterfaceSelector::Name(value.to_string()), InterfaceSelector::Address))
+    }
+}
+
+impl Display for InterfaceSelector {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            InterfaceSelector::Name(name) => { write!(f, "{name}") }
+            InterfaceSelector::Address(address) => { write!(f, "{address}") }
+        }
+    }
+}
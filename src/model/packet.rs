@@ -0,0 +1,424 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::ops::Range;
+use nom::bytes::complete::take;
+use nom::IResult;
+use nom::number::complete::{be_u16, be_u32, be_u8};
+use crate::model::link::{ETHER_TYPE_IPV4, ETHER_TYPE_IPV6, link_header, LinkHeader, LinkType};
+
+pub(crate) const IPV4_MIN_HEADER_LENGTH : usize = 20;
+pub(crate) const IPV6_HEADER_LENGTH : usize = 40;
+pub(crate) const UDP_HEADER_LENGTH : usize = 8;
+
+pub(crate) const IP_PROTOCOL_UDP : u8 = 17;
+const IP_PROTOCOL_ICMP : u8 = 1;
+const IP_PROTOCOL_IGMP : u8 = 2;
+const IP_PROTOCOL_TCP : u8 = 6;
+const IP_PROTOCOL_ICMPV6 : u8 = 58;
+const IP_PROTOCOL_SCTP : u8 = 132;
+
+const IP_PROTOCOL_NAMES : [(u8, &str); 6] = [
+    (IP_PROTOCOL_ICMP, "ICMP"),
+    (IP_PROTOCOL_IGMP, "IGMP"),
+    (IP_PROTOCOL_TCP, "TCP"),
+    (IP_PROTOCOL_UDP, "UDP"),
+    (IP_PROTOCOL_ICMPV6, "ICMPv6"),
+    (IP_PROTOCOL_SCTP, "SCTP"),
+];
+
+// IPv6 extension headers
+const IPV6_HOP_BY_HOP_OPTIONS : u8 = 0;
+const IPV6_ROUTING : u8 = 43;
+const IPV6_FRAGMENT : u8 = 44;
+const IPV6_AUTHENTICATION : u8 = 51;
+const IPV6_DESTINATION_OPTIONS : u8 = 60;
+const IPV6_MOBILITY : u8 = 135;
+const IPV6_HOST_IDENTITY : u8 = 139;
+const IPV6_SHIM6 : u8 = 140;
+
+/// A frame dissected into its link, network and transport layer headers.
+/// `payload` holds the bounds of the transport payload within the captured frame data.
+/// `missing_length` is the number of payload bytes that the length fields of the headers claim,
+/// but that were not captured because the frame was truncated to the snap length.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DissectedPacket {
+    pub link: LinkHeader,
+    pub network: NetworkHeader,
+    pub transport: TransportHeader,
+    pub payload: Range<usize>,
+    pub missing_length: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum NetworkHeader {
+    Ipv4(Ipv4Header),
+    Ipv6(Ipv6Header),
+    Other { ether_type: u16 },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Ipv4Header {
+    pub header_length: usize,
+    pub type_of_service: u8,
+    pub total_length: u16,
+    pub identification: u16,
+    pub dont_fragment: bool,
+    pub more_fragments: bool,
+    pub fragment_offset: u16,
+    pub ttl: u8,
+    pub protocol: u8,
+    pub checksum: u16,
+    pub source: Ipv4Addr,
+    pub destination: Ipv4Addr,
+    pub options: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Ipv6Header {
+    pub traffic_class: u8,
+    pub flow_label: u32,
+    pub payload_length: u16,
+    pub next_header: u8,
+    pub hop_limit: u8,
+    pub source: Ipv6Addr,
+    pub destination: Ipv6Addr,
+    /// Types of the extension headers in the chain, in order of appearance.
+    pub extension_headers: Vec<u8>,
+    pub fragment: Option<Ipv6Fragment>,
+    /// The upper-layer protocol, found at the end of the extension header chain.
+    pub protocol: u8,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Ipv6Fragment {
+    pub fragment_offset: u16,
+    pub more_fragments: bool,
+    pub identification: u32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TransportHeader {
+    Udp(UdpHeader),
+    Other { protocol: u8 },
+    /// The network layer is not IP, so there is no transport protocol to speak of.
+    None,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct UdpHeader {
+    pub source_port: u16,
+    pub destination_port: u16,
+    pub length: u16,
+    pub checksum: u16,
+}
+
+impl NetworkHeader {
+    /// The transport protocol carried by an IP packet.
+    pub fn protocol(&self) -> Option<u8> {
+        match self {
+            NetworkHeader::Ipv4(header) => { Some(header.protocol) }
+            NetworkHeader::Ipv6(header) => { Some(header.protocol) }
+            NetworkHeader::Other { .. } => { None }
+        }
+    }
+
+    /// The source address of an IP packet.
+    pub fn source(&self) -> Option<IpAddr> {
+        match self {
+            NetworkHeader::Ipv4(header) => { Some(IpAddr::V4(header.source)) }
+            NetworkHeader::Ipv6(header) => { Some(IpAddr::V6(header.source)) }
+            NetworkHeader::Other { .. } => { None }
+        }
+    }
+
+    /// The destination address of an IP packet.
+    pub fn destination(&self) -> Option<IpAddr> {
+        match self {
+            NetworkHeader::Ipv4(header) => { Some(IpAddr::V4(header.destination)) }
+            NetworkHeader::Ipv6(header) => { Some(IpAddr::V6(header.destination)) }
+            NetworkHeader::Other { .. } => { None }
+        }
+    }
+
+    /// The transport protocol of an IP packet, or `None` when it is only known after reassembly:
+    /// the extension headers that follow the Fragment header of an IPv6 fragment are part of the fragmented data.
+    pub fn upper_layer_protocol(&self) -> Option<u8> {
+        match self {
+            NetworkHeader::Ipv6(header) if self.is_fragment() && is_ipv6_extension_header(header.protocol) => { None }
+            network => { network.protocol() }
+        }
+    }
+
+    /// True for every fragment of a fragmented datagram, including the first one.
+    pub fn is_fragment(&self) -> bool {
+        match self {
+            NetworkHeader::Ipv4(header) => { header.more_fragments || header.fragment_offset != 0 }
+            NetworkHeader::Ipv6(header) => {
+                header.fragment.is_some_and(|fragment| fragment.more_fragments || fragment.fragment_offset != 0)
+            }
+            NetworkHeader::Other { .. } => { false }
+        }
+    }
+}
+
+impl TransportHeader {
+    /// The source port, for transport protocols that are dissected.
+    pub fn source_port(&self) -> Option<u16> {
+        match self {
+            TransportHeader::Udp(header) => { Some(header.source_port) }
+            TransportHeader::Other { .. } | TransportHeader::None => { None }
+        }
+    }
+
+    /// The destination port, for transport protocols that are dissected.
+    pub fn destination_port(&self) -> Option<u16> {
+        match self {
+            TransportHeader::Udp(header) => { Some(header.destination_port) }
+            TransportHeader::Other { .. } | TransportHeader::None => { None }
+        }
+    }
+}
+
+impl DissectedPacket {
+    /// Dissects a captured frame of the given link type.
+    /// A trailing frame check sequence of `fcs_length` bytes is excluded from the frame data.
+    /// The payload bounds follow the IP and UDP length fields, so that link-layer padding is excluded as well.
+    /// Returns `None` when the link or IP headers are truncated or malformed.
+    pub fn dissect(link_type: LinkType, data: &[u8], fcs_length: usize) -> Option<DissectedPacket> {
+        let data = &data[..data.len().saturating_sub(fcs_length)];
+        let (network_data, link) = link_header(link_type)(data).ok()?;
+
+        let ((transport_data, network_missing), network) = match link.ether_type {
+            ETHER_TYPE_IPV4 => {
+                let (rest, header) = ipv4_header(network_data).ok()?;
+                let length = (header.total_length as usize).saturating_sub(header.header_length);
+                (bounded(rest, length), NetworkHeader::Ipv4(header))
+            }
+            ETHER_TYPE_IPV6 => {
+                let (rest, header) = ipv6_header(network_data).ok()?;
+                let length = (header.payload_length as usize).saturating_sub(network_data.len() - rest.len() - IPV6_HEADER_LENGTH);
+                (bounded(rest, length), NetworkHeader::Ipv6(header))
+            }
+            ether_type => { ((network_data, 0), NetworkHeader::Other { ether_type }) }
+        };
+
+        let (payload, transport, transport_missing) = match network.protocol() {
+            // a fragment carries only part of the transport datagram, which is dissected after reassembly
+            Some(protocol) if network.is_fragment() => {
+                (0..transport_data.len(), TransportHeader::Other { protocol }, None)
+            }
+            protocol => { dissect_transport(protocol, transport_data) }
+        };
+        let transport_start = subslice_range(data, transport_data).start;
+
+        Some(DissectedPacket {
+            link,
+            network,
+            transport,
+            payload: transport_start + payload.start..transport_start + payload.end,
+            missing_length: transport_missing.unwrap_or(network_missing),
+        })
+    }
+}
+
+pub(crate) fn ip_protocol_name(protocol: u8) -> Option<&'static str> {
+    IP_PROTOCOL_NAMES.iter()
+        .find(|(number, _)| *number == protocol)
+        .map(|(_, name)| *name)
+}
+
+/// Parses an IP protocol by its name (case insensitive) or number.
+pub(crate) fn parse_ip_protocol(value: &str) -> Result<u8, String> {
+    IP_PROTOCOL_NAMES.iter()
+        .find(|(_, name)| name.eq_ignore_ascii_case(value))
+        .map(|(number, _)| *number)
+        .or_else(|| value.parse::<u8>().ok())
+        .ok_or_else(|| format!("Unknown IP protocol '{value}'"))
+}
+
+/// The upper-layer protocol of a reassembled IPv6 datagram that starts with the extension headers following
+/// the Fragment header, along with the length of those extension headers.
+pub(crate) fn ipv6_upper_layer(protocol: u8, data: &[u8]) -> Option<(u8, usize)> {
+    let (rest, (_, _, protocol)) = ipv6_extension_headers(protocol, data).ok()?;
+    Some((protocol, data.len() - rest.len()))
+}
+
+fn is_ipv6_extension_header(protocol: u8) -> bool {
+    matches!(protocol, IPV6_HOP_BY_HOP_OPTIONS | IPV6_ROUTING | IPV6_FRAGMENT | IPV6_AUTHENTICATION
+        | IPV6_DESTINATION_OPTIONS | IPV6_MOBILITY | IPV6_HOST_IDENTITY | IPV6_SHIM6)
+}
+
+/// Dissects the transport header of an IP payload, returning the bounds of the transport payload within `data`.
+/// For transport protocols with a length field, the number of payload bytes missing from `data` is returned as well.
+pub(crate) fn dissect_transport(protocol: Option<u8>, data: &[u8]) -> (Range<usize>, TransportHeader, Option<usize>) {
+    let (payload_data, transport, missing) = match protocol {
+        Some(IP_PROTOCOL_UDP) => {
+            match udp_header(data) {
+                Ok((rest, header)) => {
+                    let length = (header.length as usize).saturating_sub(UDP_HEADER_LENGTH);
+                    let (payload_data, missing) = bounded(rest, length);
+                    (payload_data, TransportHeader::Udp(header), Some(missing))
+                }
+                Err(_) => { (data, TransportHeader::Other { protocol: IP_PROTOCOL_UDP }, None) }
+            }
+        }
+        Some(protocol) => { (data, TransportHeader::Other { protocol }, None) }
+        None => { (data, TransportHeader::None, None) }
+    };
+    (subslice_range(data, payload_data), transport, missing)
+}
+
+/// The position of `subslice` within `data`, which it must be a part of.
+pub(crate) fn subslice_range(data: &[u8], subslice: &[u8]) -> Range<usize> {
+    let start = subslice.as_ptr() as usize - data.as_ptr() as usize;
+    start..start + subslice.len()
+}
+
+/// Limits the data to the length given by a protocol length field, and returns the number of bytes
+/// the data falls short of that length.
+/// A length of zero (as seen with segmentation offloading) or a length beyond
+/// the captured data (snaplen truncation) leaves the data as is.
+fn bounded(data: &[u8], length: usize) -> (&[u8], usize) {
+    if length == 0 || length > data.len() {
+        (data, length.saturating_sub(data.len()))
+    } else {
+        (&data[..length], 0)
+    }
+}
+
+fn ipv4_header(input: &[u8]) -> IResult<&[u8], Ipv4Header> {
+    let (input, version_ihl) = be_u8(input)?;
+    let header_length = ((version_ihl & 0x0F) as usize) * 4;
+    if version_ihl >> 4 != 4 || header_length < IPV4_MIN_HEADER_LENGTH {
+        return Err(nom::Err::Error(nom::error::Error::new(input, nom::error::ErrorKind::Verify)));
+    }
+    let (input, type_of_service) = be_u8(input)?;
+    let (input, total_length) = be_u16(input)?;
+    let (input, identification) = be_u16(input)?;
+    let (input, flags_fragment_offset) = be_u16(input)?;
+    let (input, ttl) = be_u8(input)?;
+    let (input, protocol) = be_u8(input)?;
+    let (input, checksum) = be_u16(input)?;
+    let (input, source) = ipv4_address(input)?;
+    let (input, destination) = ipv4_address(input)?;
+    let (input, options) = take(header_length - IPV4_MIN_HEADER_LENGTH)(input)?;
+
+    Ok((input, Ipv4Header {
+        header_length,
+        type_of_service,
+        total_length,
+        identification,
+        dont_fragment: flags_fragment_offset & 0x4000 != 0,
+        more_fragments: flags_fragment_offset & 0x2000 != 0,
+        fragment_offset: flags_fragment_offset & 0x1FFF,
+        ttl,
+        protocol,
+        checksum,
+        source,
+        destination,
+        options: options.to_vec(),
+    }))
+}
+
+fn ipv6_header(input: &[u8]) -> IResult<&[u8], Ipv6Header> {
+    let (input, version_class_flow) = be_u32(input)?;
+    if version_class_flow >> 28 != 6 {
+        return Err(nom::Err::Error(nom::error::Error::new(input, nom::error::ErrorKind::Verify)));
+    }
+    let (input, payload_length) = be_u16(input)?;
+    let (input, next_header) = be_u8(input)?;
+    let (input, hop_limit) = be_u8(input)?;
+    let (input, source) = ipv6_address(input)?;
+    let (input, destination) = ipv6_address(input)?;
+    let (input, (extension_headers, fragment, protocol)) = ipv6_extension_headers(next_header, input)?;
+
+    Ok((input, Ipv6Header {
+        traffic_class: ((version_class_flow >> 20) & 0xFF) as u8,
+        flow_label: version_class_flow & 0x000FFFFF,
+        payload_length,
+        next_header,
+        hop_limit,
+        source,
+        destination,
+        extension_headers,
+        fragment,
+        protocol,
+    }))
+}
+
+/// The types of the IPv6 extension headers in a chain, its Fragment header, and the protocol that ends the chain.
+type Ipv6ExtensionHeaders = (Vec<u8>, Option<Ipv6Fragment>, u8);
+
+/// Walks the chain of extension headers that starts with the header of type `protocol`, returning the types of the
+/// extension headers, the Fragment header if any, and the protocol that ends the chain. The chain ends at the
+/// Fragment header of a fragment, as the headers after it are part of the fragmented data.
+fn ipv6_extension_headers(mut protocol: u8, mut input: &[u8]) -> IResult<&[u8], Ipv6ExtensionHeaders> {
+    let mut extension_headers = vec![];
+    let mut fragment = None;
+    loop {
+        match protocol {
+            IPV6_FRAGMENT => {
+                let (rest, next) = be_u8(input)?;
+                let (rest, _reserved) = be_u8(rest)?;
+                let (rest, offset_flags) = be_u16(rest)?;
+                let (rest, identification) = be_u32(rest)?;
+                let header = Ipv6Fragment {
+                    fragment_offset: offset_flags >> 3,
+                    more_fragments: offset_flags & 0x0001 != 0,
+                    identification,
+                };
+                fragment = Some(header);
+                extension_headers.push(protocol);
+                protocol = next;
+                input = rest;
+                if header.fragment_offset != 0 || header.more_fragments {
+                    break;
+                }
+            }
+            IPV6_AUTHENTICATION => {
+                // the length of the authentication header is in 4-octet units, minus 2
+                let (_, (next, length)) = nom::sequence::pair(be_u8, be_u8)(input)?;
+                let (rest, _header) = take((length as usize + 2) * 4)(input)?;
+                extension_headers.push(protocol);
+                protocol = next;
+                input = rest;
+            }
+            IPV6_HOP_BY_HOP_OPTIONS | IPV6_ROUTING | IPV6_DESTINATION_OPTIONS
+            | IPV6_MOBILITY | IPV6_HOST_IDENTITY | IPV6_SHIM6 => {
+                // the length of the other extension headers is in 8-octet units, not counting the first 8 octets
+                let (_, (next, length)) = nom::sequence::pair(be_u8, be_u8)(input)?;
+                let (rest, _header) = take((length as usize + 1) * 8)(input)?;
+                extension_headers.push(protocol);
+                protocol = next;
+                input = rest;
+            }
+            _ => { break; }
+        }
+    }
+    Ok((input, (extension_headers, fragment, protocol)))
+}
+
+fn udp_header(input: &[u8]) -> IResult<&[u8], UdpHeader> {
+    let (input, source_port) = be_u16(input)?;
+    let (input, destination_port) = be_u16(input)?;
+    let (input, length) = be_u16(input)?;
+    let (input, checksum) = be_u16(input)?;
+
+    Ok((input, UdpHeader {
+        source_port,
+        destination_port,
+        length,
+        checksum,
+    }))
+}
+
+fn ipv4_address(input: &[u8]) -> IResult<&[u8], Ipv4Addr> {
+    let (input, address) = be_u32(input)?;
+    Ok((input, Ipv4Addr::from(address)))
+}
+
+fn ipv6_address(input: &[u8]) -> IResult<&[u8], Ipv6Addr> {
+    let (input, address) = take(16usize)(input)?;
+    let mut octets = [0u8; 16];
+    octets.copy_from_slice(address);
+    Ok((input, Ipv6Addr::from(octets)))
+}
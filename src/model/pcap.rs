@@ -1,23 +1,33 @@
 use std::fs::File;
-use std::io::{BufReader, Read};
+use std::ops::Range;
+use std::time::Duration;
 use log::trace;
+use memmap2::Mmap;
 use nom::bytes::complete::take;
-use nom::combinator::peek;
 use nom::IResult;
-use nom::multi::many1;
-use nom::number::complete::{le_u32, u16, u32};
+use nom::number::complete::{u16, u32};
 use nom::number::Endianness;
-use crate::model::{Error};
+use crate::model::{Error, ParseError, map_file};
+use crate::model::link::LinkType;
+use crate::model::packet::{DissectedPacket, subslice_range};
+use crate::model::recovery::{Damage, DamageKind, resync};
 
+/// A memory-mapped .pcap file. The packet records only index the file, their data is borrowed from the mapping.
+/// Damaged regions of the file are skipped while parsing, and reported in `damage`.
 #[derive(Debug)]
 pub struct Pcap {
     pub header: PcapFileHeader,
     pub packets: Vec<PcapPacketRecord>,
+    pub damage: Vec<Damage>,
+    /// Whether the timestamps of the packets never decrease in file order.
+    pub in_capture_order: bool,
+    data: Mmap,
 }
 
 #[derive(Debug)]
 pub struct PcapFileHeader {
     pub magic_number: PcapMagicNumber,
+    pub endianness: Endianness,
     pub major_version: u16,
     pub minor_version: u16,
     pub snap_len: u32,
@@ -26,62 +36,185 @@ pub struct PcapFileHeader {
     pub f_bit: bool,
 }
 
-#[derive(Debug)]
+/// The magic number determines the timestamp precision (and record layout) of the file.
+/// The byte order of the file follows from the order in which the magic number is written.
+#[derive(Debug, Copy, Clone, PartialEq)]
 pub enum PcapMagicNumber {
-    LeMicros,     // 0xA1B2C3D4 - Little Endian - time fraction in micro seconds
-    BeNanos,      // 0xA1B23C4D - Big Endian - time fraction in nano seconds
+    Micros,         // 0xA1B2C3D4 - time fraction in micro seconds
+    Nanos,          // 0xA1B23C4D - time fraction in nano seconds
+    ModifiedMicros, // 0xA1B2CD34 - Kuznetsov's modified pcap, time fraction in micro seconds, extended records
 }
 
-impl From<u32> for PcapMagicNumber {
-    fn from(value: u32) -> Self {
+const MAGIC_MICROS : u32 = 0xA1B2C3D4;
+const MAGIC_NANOS : u32 = 0xA1B23C4D;
+const MAGIC_MODIFIED_MICROS : u32 = 0xA1B2CD34;
+
+/// Size of the extra fields (ifindex, protocol, pkt_type, padding) in a modified pcap record header.
+const MODIFIED_RECORD_EXTENSION_LENGTH : usize = 8;
+
+/// The largest snap length used by tcpdump and Wireshark; records claiming more data are considered corrupt.
+const MAXIMUM_SNAPLEN : u32 = 262144;
+
+impl TryFrom<u32> for PcapMagicNumber {
+    type Error = Error;
+
+    fn try_from(value: u32) -> Result<Self, Self::Error> {
         match value {
-            0xA1B23C4D => PcapMagicNumber::BeNanos,
-            0xA1B2C3D4 | _ => PcapMagicNumber::LeMicros,
+            MAGIC_MICROS => Ok(PcapMagicNumber::Micros),
+            MAGIC_NANOS => Ok(PcapMagicNumber::Nanos),
+            MAGIC_MODIFIED_MICROS => Ok(PcapMagicNumber::ModifiedMicros),
+            _ => Err(ParseError::UnknownMagicNumber(value).into()),
         }
     }
 }
 
+impl PcapMagicNumber {
+    /// Determines the magic number and byte order from the first four bytes of a file.
+    pub fn detect(input: &[u8]) -> Result<(PcapMagicNumber, Endianness), Error> {
+        let magic: [u8; 4] = input.get(..4)
+            .and_then(|magic| magic.try_into().ok())
+            .ok_or(ParseError::FileTooShort)?;
+        let magic_as_le = u32::from_le_bytes(magic);
+
+        if let Ok(magic_number) = PcapMagicNumber::try_from(magic_as_le) {
+            Ok((magic_number, Endianness::Little))
+        } else if let Ok(magic_number) = PcapMagicNumber::try_from(u32::from_be_bytes(magic)) {
+            Ok((magic_number, Endianness::Big))
+        } else {
+            Err(ParseError::UnknownMagicNumber(magic_as_le).into())
+        }
+    }
+}
+
+/// `packet_data` holds the bounds of the captured packet within the file.
 #[derive(Debug)]
 pub struct PcapPacketRecord {
     pub ts_secs: u32,
     pub ts_secs_fraction: u32,
     pub captured_packet_length: u32,
     pub original_packet_length: u32,
-    pub packet_data: Vec<u8>,
+    pub packet_data: Range<usize>,
 }
 
-impl TryFrom<File> for Pcap {
-    type Error = Error;
+impl PcapFileHeader {
+    /// The length in bytes of the FCS appended to each packet, when the f bit signals its presence.
+    /// The FCS field of the header counts 16-bit words.
+    pub fn fcs_length(&self) -> usize {
+        if self.f_bit {
+            self.frame_cyclic_sequence as usize * 2
+        } else { 0 }
+    }
 
-    fn try_from(file: File) -> Result<Self, Self::Error> {
-        let mut buf = Vec::<u8>::new();
-        let mut reader = BufReader::new(file);
-        let _read = reader.read_to_end(&mut buf);
+    /// The largest captured length a valid record can have.
+    pub fn max_packet_length(&self) -> u32 {
+        self.snap_len.max(MAXIMUM_SNAPLEN)
+    }
+}
+
+impl PcapMagicNumber {
+    /// The number of timestamp fractions in a second.
+    fn fractions_per_second(&self) -> u32 {
+        match self {
+            PcapMagicNumber::Micros | PcapMagicNumber::ModifiedMicros => { 1_000_000 }
+            PcapMagicNumber::Nanos => { 1_000_000_000 }
+        }
+    }
+}
+
+impl PcapPacketRecord {
+    pub fn timestamp(&self, mode: &PcapMagicNumber) -> Duration {
+        let fraction = match mode {
+            PcapMagicNumber::Micros | PcapMagicNumber::ModifiedMicros => {
+                Duration::from_micros(self.ts_secs_fraction as u64)
+            }
+            PcapMagicNumber::Nanos => { Duration::from_nanos(self.ts_secs_fraction as u64) }
+        };
+        Duration::from_secs(self.ts_secs as u64) + fraction
+    }
+}
+
+impl Pcap {
+    pub fn packet_data(&self, index: usize) -> &[u8] {
+        &self.data[self.packets[index].packet_data.clone()]
+    }
+
+    /// Dissects a packet on demand, so the headers of all packets need not be kept in memory.
+    pub fn dissect(&self, index: usize) -> Option<DissectedPacket> {
+        let packet = &self.packets[index];
+        // a packet truncated by the snap length lost its FCS as well
+        let fcs_length = if packet.captured_packet_length < packet.original_packet_length {
+            0
+        } else { self.header.fcs_length() };
+        DissectedPacket::dissect(LinkType::from(self.header.link_type), self.packet_data(index), fcs_length)
+    }
+
+    /// The number of bytes of the packet that were not captured because of the snap length.
+    pub fn truncated_length(&self, index: usize) -> usize {
+        let packet = &self.packets[index];
+        packet.original_packet_length.saturating_sub(packet.captured_packet_length) as usize
+    }
+}
+
+impl Pcap {
+    /// Parses a pcap file, of which the magic number and byte order were detected from its first bytes.
+    pub fn parse(file: File, magic_number: PcapMagicNumber, endianness: Endianness) -> Result<Self, Error> {
+        let data = map_file(&file)?;
         trace!("start parsing pcap file");
-        match parse_pcap_file(&buf) {
-            Ok((_input, pcap)) => { Ok(pcap) }
-            Err(_err) => {
-                Err(Error::ParsePcapError) }
+        match parse_pcap_file(&data, magic_number, endianness) {
+            Ok((_input, (header, packets, damage))) if !packets.is_empty() => {
+                let in_capture_order = packets.windows(2)
+                    .all(|pair| pair[0].timestamp(&magic_number) <= pair[1].timestamp(&magic_number));
+                Ok(Pcap {
+                    header,
+                    packets,
+                    damage,
+                    in_capture_order,
+                    data,
+                })
+            }
+            Ok(_) => { Err(ParseError::NoPackets { format: "pcap" }.into()) }
+            Err(err) => { Err(ParseError::invalid("pcap", &data, err).into()) }
         }
     }
 }
 
-fn parse_pcap_file(input: &[u8]) -> IResult<&[u8], Pcap> {
-    let (input, magic_number_as_le) = peek(le_u32)(input)?;
-    let endianness = determine_endianness(magic_number_as_le);
+/// The contents of a parsed file, which the mapped file data is added to.
+type ParsedPcap = (PcapFileHeader, Vec<PcapPacketRecord>, Vec<Damage>);
+
+/// Parses all complete records of the file. A corrupt or incomplete record is skipped
+/// up to the next position where two valid records follow each other, or up to the end of the file.
+fn parse_pcap_file(input: &[u8], magic_number: PcapMagicNumber, endianness: Endianness) -> IResult<&[u8], ParsedPcap> {
+    let file = input;
+    let (mut input, header) = pcap_header(magic_number, endianness)(input)?;
+    let record = pcap_packet_record(file, magic_number, endianness, header.max_packet_length());
+    let mut packets = vec![];
+    let mut damage = vec![];
 
-    let (input, header) = pcap_header(endianness)(input)?;
-    let (input, packets) = many1(pcap_packet_record(endianness))(input)?;
-    Ok((input, Pcap {
-        header,
-        packets,
-    }))
+    while !input.is_empty() {
+        match record(input) {
+            Ok((rest, packet)) => {
+                packets.push(packet);
+                input = rest;
+            }
+            Err(err) => {
+                let skip = resync(input, |candidate| {
+                    match record(candidate) {
+                        Ok((rest, _)) => { rest.is_empty() || !matches!(record(rest), Err(nom::Err::Failure(_))) }
+                        Err(_) => { false }
+                    }
+                });
+                damage.push(Damage::at(file, input, packets.len(), DamageKind::of(&err, skip, input.len())));
+                input = &input[skip..];
+            }
+        }
+    }
+
+    Ok((input, (header, packets, damage)))
 }
 
-fn pcap_header(endianness: Endianness) -> impl Fn(&[u8]) -> IResult<&[u8], PcapFileHeader> {
+fn pcap_header(magic_number: PcapMagicNumber, endianness: Endianness) -> impl Fn(&[u8]) -> IResult<&[u8], PcapFileHeader> {
     move |input| {
-        let (input, magic_number) = u32(endianness)(input)?;
-        let magic_number = PcapMagicNumber::from(magic_number);
+        let (input, _magic_number) = u32(endianness)(input)?;
 
         let (input, major_version) = u16(endianness)(input)?;
         let (input, minor_version) = u16(endianness)(input)?;
@@ -89,10 +222,11 @@ fn pcap_header(endianness: Endianness) -> impl Fn(&[u8]) -> IResult<&[u8], PcapF
         let (input, snap_len) = u32(endianness)(input)?;
         let (input, link_type) = u32(endianness)(input)?;
         let frame_cyclic_sequence: u8 = ((link_type & 0xF0000000) >> 28) as u8;
-        let f_bit = if (link_type & 0x10000000) >> 27 == 0 { false } else { true };
+        let f_bit = link_type & 0x04000000 != 0;
 
         Ok((input, PcapFileHeader {
             magic_number,
+            endianness,
             major_version,
             minor_version,
             snap_len,
@@ -103,36 +237,32 @@ fn pcap_header(endianness: Endianness) -> impl Fn(&[u8]) -> IResult<&[u8], PcapF
     }
 }
 
-fn pcap_packet_record(endianness: Endianness) -> impl Fn(&[u8]) -> IResult<&[u8], PcapPacketRecord> {
+/// Parses a record, indexing the packet data relative to the start of the `file`.
+/// Fails when the record header is not valid, and errors when the record is cut off by the end of the input.
+fn pcap_packet_record(file: &[u8], magic_number: PcapMagicNumber, endianness: Endianness, max_packet_length: u32) -> impl Fn(&[u8]) -> IResult<&[u8], PcapPacketRecord> + '_ {
     move |input| {
+        let record_start = input;
         let (input, ts_secs) = u32(endianness)(input)?;
         let (input, ts_secs_fraction) = u32(endianness)(input)?;
         let (input, captured_packet_length) = u32(endianness)(input)?;
         let (input, original_packet_length) = u32(endianness)(input)?;
-        // TODO also parse the Ethernet and IP headers if we need to support other data link and network protocols
-        // let (input, _ethernet_header) = take(ETHERNET_HEADER_LENGTH)(input)?;
-        // let (input, _ip_header) = take(IP_HEADER_LENGTH)(input)?;
-        // TODO also parse the UDP header if needed to know what the original ports and addresses were.
-        // let (input, _udp_header) = take(UDP_HEADER_LENGTH)(input)?;
-        // trace!("captured packet length: {captured_packet_length}");
-        // trace!("original packet length: {original_packet_length}");
+        if ts_secs_fraction >= magic_number.fractions_per_second()
+            || captured_packet_length > original_packet_length
+            || captured_packet_length > max_packet_length {
+            return Err(nom::Err::Failure(nom::error::Error::new(record_start, nom::error::ErrorKind::Verify)));
+        }
+        let input = if magic_number == PcapMagicNumber::ModifiedMicros {
+            let (input, _extension) = take(MODIFIED_RECORD_EXTENSION_LENGTH)(input)?;
+            input
+        } else { input };
         let (input, packet_data) = take(captured_packet_length)(input)?;
 
-        let packet_data = packet_data.to_vec();
         Ok((input, PcapPacketRecord {
             ts_secs,
             ts_secs_fraction,
             captured_packet_length,
             original_packet_length,
-            packet_data,
+            packet_data: subslice_range(file, packet_data),
         }))
     }
 }
-
-fn determine_endianness(magic_number: u32) -> Endianness {
-    if magic_number == 0xA1B2C3D4 {
-        Endianness::Little
-    } else { // magic_number == 0xA1B23C4D
-        Endianness::Big
-    }
-}
\ No newline at end of file
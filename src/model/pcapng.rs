@@ -1,3 +1,869 @@
+use std::fmt::{Display, Formatter};
+use std::fs::File;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::ops::Range;
+use std::time::Duration;
+use log::trace;
+use memmap2::Mmap;
+use nom::bytes::complete::take;
+use nom::combinator::peek;
+use nom::IResult;
+use nom::number::complete::{be_u32, i64, u16, u32, u64};
+use nom::number::Endianness;
+use crate::model::{Error, ParseError, map_file};
+use crate::model::link::LinkType;
+use crate::model::packet::{DissectedPacket, subslice_range};
+use crate::model::recovery::{Damage, DamageKind, resync};
+
+pub(crate) const BLOCK_TYPE_SHB : u32 = 0x0A0D0D0A;
+const BLOCK_TYPE_IDB : u32 = 0x00000001;
+const BLOCK_TYPE_OPB : u32 = 0x00000002;
+const BLOCK_TYPE_SPB : u32 = 0x00000003;
+const BLOCK_TYPE_NRB : u32 = 0x00000004;
+const BLOCK_TYPE_ISB : u32 = 0x00000005;
+const BLOCK_TYPE_EPB : u32 = 0x00000006;
+
+const BYTE_ORDER_MAGIC : u32 = 0x1A2B3C4D;
+const OPTION_END_OF_OPTIONS : u16 = 0;
+const OPTION_SHB_HARDWARE : u16 = 2;
+const OPTION_SHB_OS : u16 = 3;
+const OPTION_SHB_USERAPPL : u16 = 4;
+const OPTION_IF_NAME : u16 = 2;
+const OPTION_IF_TSRESOL : u16 = 9;
+const OPTION_IF_FCSLEN : u16 = 13;
+const OPTION_IF_TSOFFSET : u16 = 14;
+const OPTION_EPB_FLAGS : u16 = 2;
+const OPTION_ISB_IFRECV : u16 = 4;
+const OPTION_ISB_IFDROP : u16 = 5;
+const NRB_RECORD_END : u16 = 0;
+const NRB_RECORD_IPV4 : u16 = 1;
+const NRB_RECORD_IPV6 : u16 = 2;
+
+/// Size of the block type and both block total length fields that surround each block body.
+const BLOCK_FRAMING_LENGTH : u32 = 12;
+
+/// A memory-mapped .pcapng file. The packet records only index the file, their data is borrowed from the mapping.
+/// Damaged regions of the file are skipped while parsing, and reported in `damage`.
+/// Packets of interfaces with different time offsets can interleave out of capture order, see `in_capture_order`.
+#[derive(Debug)]
 pub struct PcapNG {
-    pub placeholder: usize,
-}
\ No newline at end of file
+    pub sections: Vec<PcapNGSection>,
+    pub packets: Vec<PcapNGPacketRecord>,
+    pub damage: Vec<Damage>,
+    /// Whether the timestamps of the packets never decrease in file order.
+    pub in_capture_order: bool,
+    data: Mmap,
+}
+
+/// A section starts with a Section Header Block and owns all blocks up to the next one.
+/// Interface ids used by packet and statistics blocks are local to their section.
+#[derive(Debug)]
+pub struct PcapNGSection {
+    pub header: SectionHeaderBlock,
+    pub interfaces: Vec<InterfaceDescriptionBlock>,
+    pub name_resolutions: Vec<NameResolutionBlock>,
+    pub interface_statistics: Vec<InterfaceStatisticsBlock>,
+}
+
+/// Of the options, only the description of the capturing system (`shb_hardware`, `shb_os` and `shb_userappl`) is kept.
+#[derive(Debug)]
+pub struct SectionHeaderBlock {
+    pub endianness: Endianness,
+    pub major_version: u16,
+    pub minor_version: u16,
+    pub hardware: Option<String>,
+    pub os: Option<String>,
+    pub application: Option<String>,
+}
+
+#[derive(Debug)]
+pub struct InterfaceDescriptionBlock {
+    pub link_type: u16,
+    pub snap_len: u32,
+    pub name: Option<String>,
+    pub ts_resolution: TimestampResolution,
+    pub ts_offset: i64,
+    pub fcs_length: usize,
+    /// Whether the packets captured on this interface are replayed, see `select_interfaces`.
+    pub selected: bool,
+}
+
+impl InterfaceDescriptionBlock {
+    /// Dissects a packet captured on this interface.
+    /// The FCS length of the packet itself, if known, takes precedence over the `if_fcslen` of the interface.
+    pub fn dissect(&self, packet_data: &[u8], packet_fcs_length: Option<usize>, truncated: bool) -> Option<DissectedPacket> {
+        // a packet truncated by the snap length lost its FCS as well
+        let fcs_length = if truncated { 0 } else { packet_fcs_length.unwrap_or(self.fcs_length) };
+        DissectedPacket::dissect(LinkType::from(self.link_type as u32), packet_data, fcs_length)
+    }
+
+    /// Converts a raw packet timestamp of this interface to the time since the epoch,
+    /// applying both `if_tsresol` and `if_tsoffset`.
+    pub fn timestamp(&self, ts_high: u32, ts_low: u32) -> Duration {
+        let ts = ((ts_high as u64) << 32) | ts_low as u64;
+        let ts = self.ts_resolution.duration(ts);
+        let offset = Duration::from_secs(self.ts_offset.unsigned_abs());
+        if self.ts_offset < 0 {
+            ts.saturating_sub(offset)
+        } else {
+            ts.saturating_add(offset)
+        }
+    }
+}
+
+/// Units of the timestamps of an interface, as set by the `if_tsresol` option.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum TimestampResolution {
+    /// Timestamps are in units of 10^-n seconds.
+    Decimal(u8),
+    /// Timestamps are in units of 2^-n seconds.
+    Binary(u8),
+}
+
+impl Default for TimestampResolution {
+    fn default() -> Self {
+        TimestampResolution::Decimal(6)
+    }
+}
+
+impl From<u8> for TimestampResolution {
+    fn from(value: u8) -> Self {
+        if value & 0x80 == 0 {
+            TimestampResolution::Decimal(value)
+        } else {
+            TimestampResolution::Binary(value & 0x7F)
+        }
+    }
+}
+
+impl TimestampResolution {
+    pub fn duration(&self, ts: u64) -> Duration {
+        let nanos = match self {
+            TimestampResolution::Decimal(exponent) if *exponent <= 9 => {
+                ts as u128 * 10u128.pow(9 - *exponent as u32)
+            }
+            TimestampResolution::Decimal(exponent) => {
+                ts as u128 / 10u128.pow((*exponent as u32 - 9).min(38))
+            }
+            TimestampResolution::Binary(exponent) => {
+                (ts as u128 * 1_000_000_000) >> (*exponent as u32).min(127)
+            }
+        };
+        let seconds = (nanos / 1_000_000_000).min(u64::MAX as u128) as u64;
+        Duration::new(seconds, (nanos % 1_000_000_000) as u32)
+    }
+}
+
+impl Display for TimestampResolution {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TimestampResolution::Decimal(exponent) => { write!(f, "10^-{exponent} s") }
+            TimestampResolution::Binary(exponent) => { write!(f, "2^-{exponent} s") }
+        }
+    }
+}
+
+/// The names of IPv4 and IPv6 addresses; records of other types are skipped.
+#[derive(Debug)]
+pub struct NameResolutionBlock {
+    pub records: Vec<NameResolutionRecord>,
+}
+
+#[derive(Debug)]
+pub struct NameResolutionRecord {
+    pub address: IpAddr,
+    pub names: Vec<String>,
+}
+
+/// The packet counters of an interface, from the `isb_ifrecv` and `isb_ifdrop` options.
+#[derive(Debug)]
+pub struct InterfaceStatisticsBlock {
+    pub interface_id: u32,
+    pub received: Option<u64>,
+    pub dropped: Option<u64>,
+}
+
+#[derive(Debug)]
+pub struct BlockOption {
+    pub code: u16,
+    pub value: Vec<u8>,
+}
+
+/// A captured packet, read from either an Enhanced, Simple or (obsolete) Packet Block.
+/// The `timestamp` is normalised with the resolution and offset of the interface the packet was captured on.
+/// `packet_data` holds the bounds of the captured packet within the file. Of the packet options
+/// only the FCS length (from `epb_flags`) is kept, to keep the index of large files small.
+#[derive(Debug)]
+pub struct PcapNGPacketRecord {
+    pub section: usize,
+    pub interface_id: u32,
+    pub timestamp: Duration,
+    pub captured_packet_length: u32,
+    pub original_packet_length: u32,
+    pub packet_data: Range<usize>,
+    pub fcs_length: Option<usize>,
+}
+
+impl TryFrom<File> for PcapNG {
+    type Error = Error;
+
+    fn try_from(file: File) -> Result<Self, Self::Error> {
+        let data = map_file(&file)?;
+        trace!("start parsing pcapng file");
+        match parse_pcapng_file(&data) {
+            Ok((_input, (sections, packets, damage))) if !packets.is_empty() => {
+                let in_capture_order = packets.windows(2).all(|pair| pair[0].timestamp <= pair[1].timestamp);
+                Ok(PcapNG {
+                    sections,
+                    packets,
+                    damage,
+                    in_capture_order,
+                    data,
+                })
+            }
+            Ok(_) => { Err(ParseError::NoPackets { format: "pcapng" }.into()) }
+            Err(err) => { Err(ParseError::invalid("pcapng", &data, err).into()) }
+        }
+    }
+}
+
+impl PcapNG {
+    pub fn packet_data(&self, index: usize) -> &[u8] {
+        &self.data[self.packets[index].packet_data.clone()]
+    }
+
+    /// Dissects a packet on demand, so the headers of all packets need not be kept in memory.
+    pub fn dissect(&self, index: usize) -> Option<DissectedPacket> {
+        let packet = &self.packets[index];
+        self.sections[packet.section].interfaces[packet.interface_id as usize].dissect(
+            self.packet_data(index),
+            packet.fcs_length,
+            packet.captured_packet_length < packet.original_packet_length)
+    }
+
+    /// The number of bytes of the packet that were not captured because of the snap length.
+    pub fn truncated_length(&self, index: usize) -> usize {
+        let packet = &self.packets[index];
+        packet.original_packet_length.saturating_sub(packet.captured_packet_length) as usize
+    }
+
+    /// The statistics last recorded for an interface of a section, if any.
+    pub fn interface_statistics(&self, section: usize, interface_id: u32) -> Option<&InterfaceStatisticsBlock> {
+        self.sections[section].interface_statistics.iter()
+            .rfind(|statistics| statistics.interface_id == interface_id)
+    }
+
+    /// Selects the interfaces of which the packets are replayed, deselecting all others.
+    /// Interfaces are selected by `<section>:<id>` as they are listed, or by their id or their `if_name`,
+    /// which are matched in every section.
+    pub fn select_interfaces(&mut self, selection: &[String]) {
+        for (section_index, section) in self.sections.iter_mut().enumerate() {
+            for (id, interface) in section.interfaces.iter_mut().enumerate() {
+                interface.selected = selection.iter().any(|selected| {
+                    let in_section = selected.split_once(':')
+                        .and_then(|(section, id)| Some((section.parse::<usize>().ok()?, id.parse::<usize>().ok()?)));
+                    in_section == Some((section_index, id))
+                        || selected.parse::<usize>() == Ok(id)
+                        || Some(selected) == interface.name.as_ref()
+                });
+            }
+        }
+    }
+
+    /// Whether the packet is captured on a selected interface.
+    pub fn is_selected(&self, index: usize) -> bool {
+        let packet = &self.packets[index];
+        self.sections[packet.section].interfaces[packet.interface_id as usize].selected
+    }
+}
+
+/// The contents of a parsed file, which the mapped file data is added to.
+type ParsedPcapNG = (Vec<PcapNGSection>, Vec<PcapNGPacketRecord>, Vec<Damage>);
+
+/// Parses all sections of the file. Only the first Section Header Block must be intact;
+/// a later one that is corrupt is skipped up to the next valid Section Header Block.
+fn parse_pcapng_file(input: &[u8]) -> IResult<&[u8], ParsedPcapNG> {
+    let file = input;
+    let mut input = input;
+    let mut sections = vec![];
+    let mut packets = vec![];
+    let mut damage = vec![];
+
+    while !input.is_empty() {
+        match pcapng_section(file, input, sections.len(), &mut packets, &mut damage) {
+            Ok((rest, section)) => {
+                sections.push(section);
+                input = rest;
+            }
+            Err(err) if sections.is_empty() => { return Err(err); }
+            Err(err) => {
+                let skip = resync(input, |candidate| is_section_start(candidate) && is_block_start(Endianness::Little, candidate));
+                damage.push(Damage::at(file, input, packets.len(), DamageKind::of(&err, skip, input.len())));
+                input = &input[skip..];
+            }
+        }
+    }
+
+    Ok((input, (sections, packets, damage)))
+}
+
+/// Parses a Section Header Block and all following blocks of that section.
+/// Packets are appended to `packets`, as they are replayed across sections in file order;
+/// their data is indexed relative to the start of the `file`.
+/// Blocks that cannot be parsed are reported in `damage` and skipped, resyncing on the next valid block
+/// when the block length itself is corrupt.
+fn pcapng_section<'a>(file: &[u8], input: &'a [u8], section_index: usize, packets: &mut Vec<PcapNGPacketRecord>, damage: &mut Vec<Damage>) -> IResult<&'a [u8], PcapNGSection> {
+    let (input, endianness) = section_endianness(input)?;
+    let (mut input, header) = section_header_block(endianness)(input)?;
+    let mut section = PcapNGSection {
+        header,
+        interfaces: vec![],
+        name_resolutions: vec![],
+        interface_statistics: vec![],
+    };
+
+    while !input.is_empty() && !is_section_start(input) {
+        let (rest, (block_type, body)) = match raw_block(endianness)(input) {
+            Ok(block) => { block }
+            Err(err) => {
+                let skip = resync(input, |candidate| is_block_start(endianness, candidate));
+                damage.push(Damage::at(file, input, packets.len(), DamageKind::of(&err, skip, input.len())));
+                input = &input[skip..];
+                continue;
+            }
+        };
+
+        let parsed = match block_type {
+            BLOCK_TYPE_IDB => {
+                interface_description_block(endianness)(body)
+                    .map(|(_, interface)| section.interfaces.push(interface))
+            }
+            BLOCK_TYPE_EPB => {
+                enhanced_packet_block(file, endianness, section_index, &section.interfaces)(body)
+                    .map(|(_, packet)| packets.push(packet))
+            }
+            BLOCK_TYPE_SPB => {
+                let previous_timestamp = packets.last().map_or(Duration::ZERO, |packet| packet.timestamp);
+                simple_packet_block(file, endianness, section_index, &section.interfaces, previous_timestamp)(body)
+                    .map(|(_, packet)| packets.push(packet))
+            }
+            BLOCK_TYPE_OPB => {
+                obsolete_packet_block(file, endianness, section_index, &section.interfaces)(body)
+                    .map(|(_, packet)| packets.push(packet))
+            }
+            BLOCK_TYPE_NRB => {
+                name_resolution_block(endianness)(body)
+                    .map(|(_, name_resolution)| section.name_resolutions.push(name_resolution))
+            }
+            BLOCK_TYPE_ISB => {
+                interface_statistics_block(endianness)(body)
+                    .map(|(_, statistics)| section.interface_statistics.push(statistics))
+            }
+            _ => { trace!("skipping unsupported pcapng block type {block_type:#010X}"); Ok(()) }
+        };
+        if parsed.is_err() {
+            damage.push(Damage::at(file, input, packets.len(), DamageKind::Skipped { length: input.len() - rest.len() }));
+        }
+        input = rest;
+    }
+
+    Ok((input, section))
+}
+
+/// The SHB block type reads the same in both byte orders.
+fn is_section_start(input: &[u8]) -> bool {
+    input.starts_with(&BLOCK_TYPE_SHB.to_le_bytes())
+}
+
+/// Whether a complete block of a known type starts at the input, used to resync after corrupt data.
+/// A Section Header Block is checked in its own byte order.
+fn is_block_start(endianness: Endianness, input: &[u8]) -> bool {
+    let endianness = if is_section_start(input) {
+        match section_endianness(input) {
+            Ok((_, endianness)) => { endianness }
+            Err(_) => { return false; }
+        }
+    } else { endianness };
+    match raw_block(endianness)(input) {
+        Ok((_, (block_type, _))) => {
+            matches!(block_type, BLOCK_TYPE_SHB | BLOCK_TYPE_IDB | BLOCK_TYPE_OPB | BLOCK_TYPE_SPB
+                | BLOCK_TYPE_NRB | BLOCK_TYPE_ISB | BLOCK_TYPE_EPB)
+        }
+        Err(_) => { false }
+    }
+}
+
+/// The byte-order magic directly follows the block type and block total length of the SHB.
+/// Fails when the magic reads as 0x1A2B3C4D in neither byte order.
+fn section_endianness(input: &[u8]) -> IResult<&[u8], Endianness> {
+    let (_, (_block_type, _block_length, byte_order_magic)) = peek(
+        nom::sequence::tuple((be_u32, be_u32, be_u32)))(input)?;
+    match byte_order_magic {
+        BYTE_ORDER_MAGIC => { Ok((input, Endianness::Big)) }
+        magic if magic.swap_bytes() == BYTE_ORDER_MAGIC => { Ok((input, Endianness::Little)) }
+        _ => { Err(nom::Err::Failure(nom::error::Error::new(input, nom::error::ErrorKind::Verify))) }
+    }
+}
+
+/// Splits off a single block, returning its type and body without the surrounding length fields.
+/// Fails when the block lengths are not valid, and errors when the block is cut off by the end of the input.
+fn raw_block(endianness: Endianness) -> impl Fn(&[u8]) -> IResult<&[u8], (u32, &[u8])> {
+    move |input| {
+        let block_start = input;
+        let corrupt = || nom::Err::Failure(nom::error::Error::new(block_start, nom::error::ErrorKind::LengthValue));
+        let (input, block_type) = u32(endianness)(input)?;
+        let (input, block_total_length) = u32(endianness)(input)?;
+        if block_total_length % 4 != 0 {
+            return Err(corrupt());
+        }
+        let body_length = block_total_length.checked_sub(BLOCK_FRAMING_LENGTH).ok_or_else(corrupt)?;
+        let (input, body) = take(body_length)(input)?;
+        let (input, trailing_block_total_length) = u32(endianness)(input)?;
+        if trailing_block_total_length != block_total_length {
+            return Err(corrupt());
+        }
+        Ok((input, (block_type, body)))
+    }
+}
+
+fn section_header_block(endianness: Endianness) -> impl Fn(&[u8]) -> IResult<&[u8], SectionHeaderBlock> {
+    move |input| {
+        let (input, (_block_type, body)) = raw_block(endianness)(input)?;
+        let (body, _byte_order_magic) = u32(endianness)(body)?;
+        let (body, major_version) = u16(endianness)(body)?;
+        let (body, minor_version) = u16(endianness)(body)?;
+        let (body, _section_length) = i64(endianness)(body)?;
+        let (_, options) = block_options(endianness)(body)?;
+
+        Ok((input, SectionHeaderBlock {
+            endianness,
+            major_version,
+            minor_version,
+            hardware: option_string(&options, OPTION_SHB_HARDWARE),
+            os: option_string(&options, OPTION_SHB_OS),
+            application: option_string(&options, OPTION_SHB_USERAPPL),
+        }))
+    }
+}
+
+fn interface_description_block(endianness: Endianness) -> impl Fn(&[u8]) -> IResult<&[u8], InterfaceDescriptionBlock> {
+    move |body| {
+        let (body, link_type) = u16(endianness)(body)?;
+        let (body, _reserved) = u16(endianness)(body)?;
+        let (body, snap_len) = u32(endianness)(body)?;
+        let (body, options) = block_options(endianness)(body)?;
+
+        let name = option_string(&options, OPTION_IF_NAME);
+        let ts_resolution = option_value(&options, OPTION_IF_TSRESOL)
+            .and_then(|value| value.first())
+            .map(|value| TimestampResolution::from(*value))
+            .unwrap_or_default();
+        // if_fcslen is expressed in bits
+        let fcs_length = option_value(&options, OPTION_IF_FCSLEN)
+            .and_then(|value| value.first())
+            .map(|bits| *bits as usize / 8)
+            .unwrap_or(0);
+        let ts_offset = option_value(&options, OPTION_IF_TSOFFSET)
+            .and_then(|value| i64::<&[u8], nom::error::Error<&[u8]>>(endianness)(value).ok())
+            .map(|(_, offset)| offset)
+            .unwrap_or(0);
+
+        Ok((body, InterfaceDescriptionBlock {
+            link_type,
+            snap_len,
+            name,
+            ts_resolution,
+            ts_offset,
+            fcs_length,
+            selected: true,
+        }))
+    }
+}
+
+fn enhanced_packet_block<'a>(file: &'a [u8], endianness: Endianness, section: usize, interfaces: &'a [InterfaceDescriptionBlock]) -> impl Fn(&[u8]) -> IResult<&[u8], PcapNGPacketRecord> + 'a {
+    move |body| {
+        let (body, interface_id) = u32(endianness)(body)?;
+        let (body, ts_high) = u32(endianness)(body)?;
+        let (body, ts_low) = u32(endianness)(body)?;
+        let (body, captured_packet_length) = u32(endianness)(body)?;
+        let (body, original_packet_length) = u32(endianness)(body)?;
+        let (body, packet_data) = padded_data(captured_packet_length)(body)?;
+        let (body, options) = block_options(endianness)(body)?;
+        let interface = packet_interface(interfaces, interface_id, body)?;
+
+        Ok((body, PcapNGPacketRecord {
+            section,
+            interface_id,
+            timestamp: interface.timestamp(ts_high, ts_low),
+            captured_packet_length,
+            original_packet_length,
+            packet_data: subslice_range(file, packet_data),
+            fcs_length: epb_fcs_length(endianness, &options),
+        }))
+    }
+}
+
+/// Simple Packet Blocks always refer to the first interface of the section,
+/// and store at most `snap_len` bytes of the original packet.
+/// They carry no timestamp, so they take the `timestamp` of the packet before them and are replayed right after it,
+/// which keeps the packets in capture order.
+fn simple_packet_block<'a>(file: &'a [u8], endianness: Endianness, section: usize, interfaces: &'a [InterfaceDescriptionBlock], timestamp: Duration) -> impl Fn(&[u8]) -> IResult<&[u8], PcapNGPacketRecord> + 'a {
+    move |body| {
+        let interface = packet_interface(interfaces, 0, body)?;
+        let snap_len = interface.snap_len;
+        let (body, original_packet_length) = u32(endianness)(body)?;
+        let captured_packet_length = if snap_len == 0 {
+            original_packet_length
+        } else {
+            original_packet_length.min(snap_len)
+        }.min(body.len() as u32);
+        let (body, packet_data) = padded_data(captured_packet_length)(body)?;
+
+        Ok((body, PcapNGPacketRecord {
+            section,
+            interface_id: 0,
+            timestamp,
+            captured_packet_length,
+            original_packet_length,
+            packet_data: subslice_range(file, packet_data),
+            fcs_length: None,
+        }))
+    }
+}
+
+fn obsolete_packet_block<'a>(file: &'a [u8], endianness: Endianness, section: usize, interfaces: &'a [InterfaceDescriptionBlock]) -> impl Fn(&[u8]) -> IResult<&[u8], PcapNGPacketRecord> + 'a {
+    move |body| {
+        let (body, interface_id) = u16(endianness)(body)?;
+        let (body, _drops_count) = u16(endianness)(body)?;
+        let (body, ts_high) = u32(endianness)(body)?;
+        let (body, ts_low) = u32(endianness)(body)?;
+        let (body, captured_packet_length) = u32(endianness)(body)?;
+        let (body, original_packet_length) = u32(endianness)(body)?;
+        let (body, packet_data) = padded_data(captured_packet_length)(body)?;
+        let (body, _options) = block_options(endianness)(body)?;
+        let interface = packet_interface(interfaces, interface_id as u32, body)?;
+
+        Ok((body, PcapNGPacketRecord {
+            section,
+            interface_id: interface_id as u32,
+            timestamp: interface.timestamp(ts_high, ts_low),
+            captured_packet_length,
+            original_packet_length,
+            packet_data: subslice_range(file, packet_data),
+            fcs_length: None,
+        }))
+    }
+}
+
+fn name_resolution_block(endianness: Endianness) -> impl Fn(&[u8]) -> IResult<&[u8], NameResolutionBlock> {
+    move |body| {
+        let mut body = body;
+        let mut records = vec![];
+        while !body.is_empty() {
+            let (rest, record_type) = u16(endianness)(body)?;
+            let (rest, record_length) = u16(endianness)(rest)?;
+            let (rest, value) = padded_data(record_length as u32)(rest)?;
+            body = rest;
+
+            match record_type {
+                NRB_RECORD_END => { break; }
+                NRB_RECORD_IPV4 if value.len() >= 4 => {
+                    let address = IpAddr::V4(Ipv4Addr::new(value[0], value[1], value[2], value[3]));
+                    records.push(NameResolutionRecord { address, names: null_terminated_strings(&value[4..]) });
+                }
+                NRB_RECORD_IPV6 if value.len() >= 16 => {
+                    let mut octets = [0u8; 16];
+                    octets.copy_from_slice(&value[..16]);
+                    let address = IpAddr::V6(Ipv6Addr::from(octets));
+                    records.push(NameResolutionRecord { address, names: null_terminated_strings(&value[16..]) });
+                }
+                _ => { trace!("skipping name resolution record of type {record_type}"); }
+            }
+        }
+        let (body, _options) = block_options(endianness)(body)?;
+
+        Ok((body, NameResolutionBlock {
+            records,
+        }))
+    }
+}
+
+fn interface_statistics_block(endianness: Endianness) -> impl Fn(&[u8]) -> IResult<&[u8], InterfaceStatisticsBlock> {
+    move |body| {
+        let (body, interface_id) = u32(endianness)(body)?;
+        let (body, _ts_high) = u32(endianness)(body)?;
+        let (body, _ts_low) = u32(endianness)(body)?;
+        let (body, options) = block_options(endianness)(body)?;
+        let counter = |code| option_value(&options, code)
+            .and_then(|value| u64::<&[u8], nom::error::Error<&[u8]>>(endianness)(value).ok())
+            .map(|(_, count)| count);
+
+        Ok((body, InterfaceStatisticsBlock {
+            interface_id,
+            received: counter(OPTION_ISB_IFRECV),
+            dropped: counter(OPTION_ISB_IFDROP),
+        }))
+    }
+}
+
+/// Packets must refer to an interface that is described earlier in the same section.
+fn packet_interface<'a, 'b>(interfaces: &'a [InterfaceDescriptionBlock], interface_id: u32, input: &'b [u8]) -> Result<&'a InterfaceDescriptionBlock, nom::Err<nom::error::Error<&'b [u8]>>> {
+    interfaces.get(interface_id as usize)
+        .ok_or_else(|| nom::Err::Failure(nom::error::Error::new(input, nom::error::ErrorKind::Verify)))
+}
+
+/// The FCS length in bytes, as recorded in bits 5-8 of the `epb_flags` option of an Enhanced Packet Block.
+fn epb_fcs_length(endianness: Endianness, options: &[BlockOption]) -> Option<usize> {
+    option_value(options, OPTION_EPB_FLAGS)
+        .and_then(|value| u32::<&[u8], nom::error::Error<&[u8]>>(endianness)(value).ok())
+        .map(|(_, flags)| ((flags >> 5) & 0x0F) as usize)
+        .filter(|fcs_length| *fcs_length > 0)
+}
+
+fn option_value(options: &[BlockOption], code: u16) -> Option<&[u8]> {
+    options.iter()
+        .find(|option| option.code == code)
+        .map(|option| option.value.as_slice())
+}
+
+fn option_string(options: &[BlockOption], code: u16) -> Option<String> {
+    option_value(options, code)
+        .map(|value| String::from_utf8_lossy(value).trim_end_matches('\0').to_string())
+}
+
+/// Options run until the end of the block body, or until an `opt_endofopt` option.
+fn block_options(endianness: Endianness) -> impl Fn(&[u8]) -> IResult<&[u8], Vec<BlockOption>> {
+    move |input| {
+        let mut input = input;
+        let mut options = vec![];
+        // an option header takes 4 bytes, anything shorter is trailing padding
+        while input.len() >= 4 {
+            let (rest, code) = u16(endianness)(input)?;
+            let (rest, length) = u16(endianness)(rest)?;
+            let (rest, value) = padded_data(length as u32)(rest)?;
+            input = rest;
+
+            if code == OPTION_END_OF_OPTIONS {
+                break;
+            }
+            options.push(BlockOption { code, value: value.to_vec() });
+        }
+        Ok((input, options))
+    }
+}
+
+/// Takes `length` bytes of data, and skips the padding up to the next 32-bit boundary.
+fn padded_data(length: u32) -> impl Fn(&[u8]) -> IResult<&[u8], &[u8]> {
+    move |input| {
+        let (input, data) = take(length)(input)?;
+        let padding = ((4 - (length % 4)) % 4) as usize;
+        let (input, _padding) = take(padding.min(input.len()))(input)?;
+        Ok((input, data))
+    }
+}
+
+fn null_terminated_strings(input: &[u8]) -> Vec<String> {
+    input.split(|byte| *byte == 0)
+        .filter(|name| !name.is_empty())
+        .map(|name| String::from_utf8_lossy(name).to_string())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::model::Recording;
+    use super::*;
+
+    fn block(endianness: Endianness, block_type: u32, body: &[u8]) -> Vec<u8> {
+        let mut body = body.to_vec();
+        body.resize(body.len().div_ceil(4) * 4, 0);
+        let length = body.len() as u32 + BLOCK_FRAMING_LENGTH;
+        [word(endianness, block_type), word(endianness, length), body, word(endianness, length)].concat()
+    }
+
+    fn word(endianness: Endianness, value: u32) -> Vec<u8> {
+        match endianness {
+            Endianness::Big => { value.to_be_bytes().to_vec() }
+            _ => { value.to_le_bytes().to_vec() }
+        }
+    }
+
+    fn half(endianness: Endianness, value: u16) -> Vec<u8> {
+        match endianness {
+            Endianness::Big => { value.to_be_bytes().to_vec() }
+            _ => { value.to_le_bytes().to_vec() }
+        }
+    }
+
+    fn option(endianness: Endianness, code: u16, value: &[u8]) -> Vec<u8> {
+        let mut value = value.to_vec();
+        let length = value.len() as u16;
+        value.resize(value.len().div_ceil(4) * 4, 0);
+        [half(endianness, code), half(endianness, length), value].concat()
+    }
+
+    fn shb(endianness: Endianness) -> Vec<u8> {
+        let body = [word(endianness, BYTE_ORDER_MAGIC), half(endianness, 1), half(endianness, 0), vec![0xFF; 8],
+            option(endianness, OPTION_SHB_USERAPPL, b"tests")].concat();
+        block(endianness, BLOCK_TYPE_SHB, &body)
+    }
+
+    fn idb(endianness: Endianness, snap_len: u32, options: &[u8]) -> Vec<u8> {
+        let body = [half(endianness, 1), half(endianness, 0), word(endianness, snap_len), options.to_vec()].concat();
+        block(endianness, BLOCK_TYPE_IDB, &body)
+    }
+
+    fn epb(endianness: Endianness, interface_id: u32, ts: u64, data: &[u8]) -> Vec<u8> {
+        let length = data.len() as u32;
+        let body = [word(endianness, interface_id), word(endianness, (ts >> 32) as u32), word(endianness, ts as u32),
+            word(endianness, length), word(endianness, length), data.to_vec()].concat();
+        block(endianness, BLOCK_TYPE_EPB, &body)
+    }
+
+    #[test]
+    fn parses_both_byte_orders() {
+        for endianness in [Endianness::Little, Endianness::Big] {
+            let file = [shb(endianness), idb(endianness, 0, &option(endianness, OPTION_IF_NAME, b"eth0")),
+                epb(endianness, 0, 1_500_000, b"hello")].concat();
+            let (_, (sections, packets, damage)) = parse_pcapng_file(&file).unwrap();
+
+            assert_eq!(sections.len(), 1);
+            assert_eq!(sections[0].header.endianness, endianness);
+            assert_eq!(sections[0].header.application.as_deref(), Some("tests"));
+            assert_eq!(sections[0].interfaces[0].name.as_deref(), Some("eth0"));
+            assert_eq!(packets.len(), 1);
+            assert_eq!(packets[0].timestamp, Duration::from_micros(1_500_000));
+            assert_eq!(&file[packets[0].packet_data.clone()], b"hello");
+            assert!(damage.is_empty());
+        }
+    }
+
+    #[test]
+    fn rejects_an_unknown_byte_order_magic() {
+        let mut file = [shb(Endianness::Little), idb(Endianness::Little, 0, &[]), epb(Endianness::Little, 0, 0, b"x")].concat();
+        file[8..12].copy_from_slice(&0x11223344u32.to_le_bytes());
+
+        assert!(parse_pcapng_file(&file).is_err());
+    }
+
+    #[test]
+    fn applies_the_timestamp_resolution_and_offset_of_the_interface() {
+        let e = Endianness::Little;
+        let options = [option(e, OPTION_IF_TSRESOL, &[9]), option(e, OPTION_IF_TSOFFSET, &10i64.to_le_bytes())].concat();
+        let file = [shb(e), idb(e, 0, &options), epb(e, 0, 1_000_000_001, b"x")].concat();
+        let (_, (_, packets, _)) = parse_pcapng_file(&file).unwrap();
+
+        assert_eq!(packets[0].timestamp, Duration::new(11, 1));
+    }
+
+    #[test]
+    fn binary_timestamp_resolution() {
+        assert_eq!(TimestampResolution::from(0x80 | 10).duration(1024 + 512), Duration::from_millis(1500));
+    }
+
+    #[test]
+    fn simple_packet_blocks_are_capped_at_the_snap_length() {
+        let e = Endianness::Little;
+        let spb = block(e, BLOCK_TYPE_SPB, &[word(e, 6), b"abcdef".to_vec()].concat());
+        let file = [shb(e), idb(e, 4, &[]), spb].concat();
+        let (_, (_, packets, _)) = parse_pcapng_file(&file).unwrap();
+
+        assert_eq!(&file[packets[0].packet_data.clone()], b"abcd");
+        assert_eq!(packets[0].original_packet_length, 6);
+        assert_eq!(packets[0].timestamp, Duration::ZERO);
+    }
+
+    fn recording(file: &[u8]) -> Recording {
+        let mut temp_file = tempfile::tempfile().unwrap();
+        std::io::Write::write_all(&mut temp_file, file).unwrap();
+        Recording::PCAPNG(PcapNG::try_from(temp_file).unwrap())
+    }
+
+    #[test]
+    fn simple_packet_blocks_take_the_timestamp_of_the_packet_before_them() {
+        let e = Endianness::Little;
+        let spb = block(e, BLOCK_TYPE_SPB, &[word(e, 1), b"b".to_vec()].concat());
+        let recording = recording(&[shb(e), idb(e, 0, &[]), epb(e, 0, 1_000_000, b"a"), spb, epb(e, 0, 3_000_000, b"c")].concat());
+
+        assert_eq!(recording.packet_timestamp(1), Duration::from_secs(1));
+        assert!(recording.in_capture_order());
+        assert_eq!(recording.records_before(|timestamp| timestamp >= Duration::from_secs(2)), 2);
+    }
+
+    #[test]
+    fn finds_times_in_file_order_when_packets_are_not_in_capture_order() {
+        let e = Endianness::Little;
+        let offset = option(e, OPTION_IF_TSOFFSET, &10i64.to_le_bytes());
+        let recording = recording(&[shb(e), idb(e, 0, &[]), idb(e, 0, &offset),
+            epb(e, 1, 0, b"a"), epb(e, 0, 5_000_000, b"b"), epb(e, 0, 12_000_000, b"c")].concat());
+
+        assert!(!recording.in_capture_order());
+        assert_eq!(recording.records_before(|timestamp| timestamp >= Duration::from_secs(10)), 0);
+        assert_eq!(recording.records_before(|timestamp| timestamp > Duration::from_secs(10)), 2);
+    }
+
+    #[test]
+    fn parses_name_resolution_and_interface_statistics() {
+        let e = Endianness::Little;
+        let record = [half(e, NRB_RECORD_IPV4), half(e, 11), vec![10, 0, 0, 2], b"host-b\0".to_vec(), vec![0]].concat();
+        let nrb = block(e, BLOCK_TYPE_NRB, &[record, half(e, NRB_RECORD_END), half(e, 0)].concat());
+        let isb_body = [word(e, 0), word(e, 0), word(e, 0),
+            option(e, OPTION_ISB_IFRECV, &8u64.to_le_bytes()), option(e, OPTION_ISB_IFDROP, &3u64.to_le_bytes())].concat();
+        let file = [shb(e), idb(e, 0, &[]), nrb, epb(e, 0, 0, b"x"), block(e, BLOCK_TYPE_ISB, &isb_body)].concat();
+        let (_, (sections, _, damage)) = parse_pcapng_file(&file).unwrap();
+
+        let record = &sections[0].name_resolutions[0].records[0];
+        assert_eq!(record.address, IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2)));
+        assert_eq!(record.names, vec!["host-b".to_string()]);
+        let statistics = &sections[0].interface_statistics[0];
+        assert_eq!((statistics.received, statistics.dropped), (Some(8), Some(3)));
+        assert!(damage.is_empty());
+    }
+
+    #[test]
+    fn interface_ids_are_local_to_their_section() {
+        let e = Endianness::Little;
+        let options = option(e, OPTION_IF_TSRESOL, &[3]);
+        let file = [shb(e), idb(e, 0, &[]), epb(e, 0, 1, b"a"),
+            shb(e), idb(e, 0, &options), epb(e, 0, 1, b"b")].concat();
+        let (_, (sections, packets, _)) = parse_pcapng_file(&file).unwrap();
+
+        assert_eq!(sections.len(), 2);
+        assert_eq!((packets[0].section, packets[0].timestamp), (0, Duration::from_micros(1)));
+        assert_eq!((packets[1].section, packets[1].timestamp), (1, Duration::from_millis(1)));
+    }
+
+    #[test]
+    fn selects_interfaces_by_section_and_id_or_by_id_or_name() {
+        let e = Endianness::Little;
+        let file = [shb(e), idb(e, 0, &option(e, OPTION_IF_NAME, b"eth0")), idb(e, 0, &[]), epb(e, 0, 0, b"a"), epb(e, 1, 0, b"b"),
+            shb(e), idb(e, 0, &[]), epb(e, 0, 0, b"c")].concat();
+        let selected = |selection: &[&str]| {
+            let (_, (sections, packets, damage)) = parse_pcapng_file(&file).unwrap();
+            let data = memmap2::MmapMut::map_anon(file.len()).unwrap().make_read_only().unwrap();
+            let mut pcapng = PcapNG { sections, packets, damage, in_capture_order: true, data };
+            pcapng.select_interfaces(&selection.iter().map(|selected| selected.to_string()).collect::<Vec<_>>());
+            (0..pcapng.packets.len()).filter(|index| pcapng.is_selected(*index))
+                .map(|index| file[pcapng.packets[index].packet_data.clone()][0]).collect::<Vec<u8>>()
+        };
+
+        assert_eq!(selected(&["1:0"]), b"c");
+        assert_eq!(selected(&["0:1"]), b"b");
+        assert_eq!(selected(&["0"]), b"ac");
+        assert_eq!(selected(&["eth0", "0:1"]), b"ab");
+    }
+
+    #[test]
+    fn skips_corrupt_blocks_and_packets_of_unknown_interfaces() {
+        let e = Endianness::Little;
+        let mut corrupt = epb(e, 0, 0, b"bad");
+        let last = corrupt.len() - 4;
+        corrupt[last..].copy_from_slice(&word(e, 0));
+        let file = [shb(e), idb(e, 0, &[]), corrupt, epb(e, 7, 0, b"unknown"), epb(e, 0, 0, b"good")].concat();
+        let (_, (_, packets, damage)) = parse_pcapng_file(&file).unwrap();
+
+        assert_eq!(packets.len(), 1);
+        assert_eq!(&file[packets[0].packet_data.clone()], b"good");
+        assert_eq!(damage.len(), 2);
+    }
+}
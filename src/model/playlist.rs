@@ -0,0 +1,461 @@
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+use std::net::IpAddr;
+use std::ops::Range;
+use std::str::FromStr;
+use std::time::Duration;
+use log::trace;
+use crate::model::link::ether_type_name;
+use crate::model::filter::{Filter, FilterInput};
+use crate::model::packet::{dissect_transport, ip_protocol_name, ipv6_upper_layer, NetworkHeader};
+use crate::model::reassembly::{FragmentPosition, IncompleteDatagram, Reassembler};
+use crate::model::{Error, Recording, SeekTarget};
+
+/// The datagrams to replay, read in order from a window of the records of a recording. Records are only
+/// dissected when the player reaches them, so that playback starts right away and memory stays flat
+/// regardless of the size of the recording.
+/// Only packets of the selected IP `protocols` that match the `filter` are replayed; IP fragments are
+/// reassembled on the way, and packets truncated by the snap length are handled according to the `truncation` policy.
+pub(crate) struct Playlist {
+    protocols: Vec<u8>,
+    filter: Option<Filter>,
+    truncation: TruncationPolicy,
+    window: Range<usize>,
+    next_record: usize,
+    reassembler: Reassembler,
+    // the records before this one are counted in the statistics, so that replaying them again does not count them twice
+    counted_until: usize,
+    // the record of the packet last returned by `next`, when that record was counted
+    last_counted: Option<usize>,
+    pub statistics: PlaylistStatistics,
+}
+
+/// What happened to the records the playlist read. Every record is counted once, the first time it is read.
+/// `replayed` counts the datagrams returned to replay, `truncated` the selected packets of which the payload
+/// was cut off by the snap length, and `matched` the packets (or reassembled datagrams) that matched the filter.
+/// The packets outside the window are counted as skipped up front.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct PlaylistStatistics {
+    pub read: usize,
+    pub replayed: usize,
+    pub skipped: BTreeMap<SkipReason, usize>,
+    pub incomplete_datagrams: Vec<IncompleteDatagram>,
+    pub truncated: usize,
+    pub matched: usize,
+}
+
+/// What to replay for a packet that was captured shorter than it was sent (snap length truncation).
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub(crate) enum TruncationPolicy {
+    Skip,
+    /// Replays the captured part of the payload.
+    Truncate,
+    /// Pads the captured part of the payload with zeros, up to the length of the original payload.
+    Pad,
+}
+
+impl FromStr for TruncationPolicy {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_ascii_lowercase().as_str() {
+            "skip" => { Ok(TruncationPolicy::Skip) }
+            "truncate" => { Ok(TruncationPolicy::Truncate) }
+            "pad" => { Ok(TruncationPolicy::Pad) }
+            _ => { Err(format!("unknown policy '{value}', expected skip, truncate or pad")) }
+        }
+    }
+}
+
+impl Display for TruncationPolicy {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TruncationPolicy::Skip => { write!(f, "skip") }
+            TruncationPolicy::Truncate => { write!(f, "truncate") }
+            TruncationPolicy::Pad => { write!(f, "pad") }
+        }
+    }
+}
+
+/// Why a packet of the recording is not replayed.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) enum SkipReason {
+    /// Captured on an interface that is not selected.
+    OtherInterface,
+    /// The link or IP headers are truncated, malformed or of an unsupported link type.
+    Undecodable,
+    NotIp { ether_type: u16 },
+    NotSelected { protocol: u8 },
+    IncompleteDatagram,
+    Truncated,
+    NotMatched,
+    OutsideWindow,
+    /// Neither the packet nor the destination it is sent to has a port.
+    NoDestinationPort,
+}
+
+impl Display for SkipReason {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SkipReason::OtherInterface => { write!(f, "Captured on another interface") }
+            SkipReason::Undecodable => { write!(f, "Cannot decode link or IP headers") }
+            SkipReason::NotIp { ether_type } => {
+                match ether_type_name(*ether_type) {
+                    Some(name) => { write!(f, "Not IP ({name})") }
+                    None => { write!(f, "Not IP (EtherType {ether_type:#06x})") }
+                }
+            }
+            SkipReason::NotSelected { protocol } => {
+                match ip_protocol_name(*protocol) {
+                    Some(name) => { write!(f, "Protocol not selected ({name})") }
+                    None => { write!(f, "Protocol not selected (IP protocol {protocol})") }
+                }
+            }
+            SkipReason::IncompleteDatagram => { write!(f, "Fragment of an incomplete datagram") }
+            SkipReason::Truncated => { write!(f, "Truncated by the snap length") }
+            SkipReason::NotMatched => { write!(f, "Not matched by the filter") }
+            SkipReason::OutsideWindow => { write!(f, "Outside the selected window") }
+            SkipReason::NoDestinationPort => { write!(f, "No destination port") }
+        }
+    }
+}
+
+/// A single datagram to replay. `record` is the index of the record in the recording it was
+/// taken from; for reassembled datagrams this is the record of the fragment that completed it.
+/// The addresses and ports are those as captured; ports are only known for UDP.
+#[derive(Debug)]
+pub(crate) struct ReplayPacket {
+    pub record: usize,
+    pub timestamp: Duration,
+    pub payload: ReplayPayload,
+    pub source: IpAddr,
+    pub source_port: Option<u16>,
+    pub destination: IpAddr,
+    pub destination_port: Option<u16>,
+}
+
+#[derive(Debug)]
+pub(crate) enum ReplayPayload {
+    /// Bounds of the payload within the captured frame of the record.
+    Captured(Range<usize>),
+    Reassembled(Vec<u8>),
+    /// The captured payload of a truncated packet, padded with zeros.
+    Padded(Vec<u8>),
+}
+
+impl ReplayPacket {
+    pub(crate) fn payload<'a>(&'a self, recording: &'a Recording) -> &'a [u8] {
+        match &self.payload {
+            ReplayPayload::Captured(range) => { &recording.packet_data(self.record)[range.clone()] }
+            ReplayPayload::Reassembled(data) | ReplayPayload::Padded(data) => { data.as_slice() }
+        }
+    }
+}
+
+impl Playlist {
+    /// Creates a playlist over all records of the recording. No records are read until `next` is called.
+    pub(crate) fn new(recording: &Recording, protocols: Vec<u8>, filter: Option<Filter>, truncation: TruncationPolicy) -> Playlist {
+        Playlist {
+            protocols,
+            filter,
+            truncation,
+            window: 0..recording.packet_count(),
+            next_record: 0,
+            reassembler: Reassembler::default(),
+            counted_until: 0,
+            last_counted: None,
+            statistics: PlaylistStatistics::default(),
+        }
+    }
+
+    /// Restricts the playlist to the packets captured from `start` up to and including `end`.
+    /// Packet numbers are the frame numbers of the capture, and time offsets are taken from the first record
+    /// of the recording for a forward offset, or from its last record for a backward offset.
+    /// The packets outside the window are counted as skipped. Errors when the window selects no records.
+    pub(crate) fn select_window(&mut self, recording: &Recording, start: Option<SeekTarget>, end: Option<SeekTarget>) -> Result<(), Error> {
+        let count = recording.packet_count();
+        let (first_ts, last_ts) = (recording.packet_timestamp(0), recording.packet_timestamp(count - 1));
+        let time_of = |target: SeekTarget| match target {
+            SeekTarget::Packet(_) => { Duration::ZERO }
+            SeekTarget::Offset { offset, backward: false } => { first_ts + offset }
+            SeekTarget::Offset { offset, backward: true } => { last_ts.saturating_sub(offset) }
+            SeekTarget::Timestamp(timestamp) => { timestamp }
+        };
+
+        let start_record = match start {
+            None => { 0 }
+            Some(SeekTarget::Packet(index)) => { index }
+            Some(target) => { recording.records_before(|timestamp| timestamp >= time_of(target)) }
+        };
+        let end_record = match end {
+            None => { count }
+            Some(SeekTarget::Packet(index)) => { index + 1 }
+            Some(target) => { recording.records_before(|timestamp| timestamp > time_of(target)) }
+        }.min(count);
+
+        let window = || format!("{} - {}",
+            start.map_or("start".to_string(), |start| start.to_string()),
+            end.map_or("end".to_string(), |end| end.to_string()));
+        if start_record >= count {
+            return Err(Error::SelectPackets(format!("the window {} starts after the last packet (#{count})", window())));
+        }
+        if start_record >= end_record {
+            return Err(Error::SelectPackets(format!("the window {} ends before it starts", window())));
+        }
+
+        self.window = start_record..end_record;
+        self.next_record = start_record;
+        self.counted_until = start_record;
+        let outside = count - self.window.len();
+        if outside > 0 {
+            self.statistics.skipped.insert(SkipReason::OutsideWindow, outside);
+        }
+        Ok(())
+    }
+
+    /// The records the packets are read from.
+    pub(crate) fn window(&self) -> Range<usize> {
+        self.window.clone()
+    }
+
+    /// The number of records in the window that were captured shorter than they were sent. This is taken from the
+    /// record index, without dissecting the packets, so it includes the packets that are skipped for other reasons.
+    pub(crate) fn truncated_records(&self, recording: &Recording) -> usize {
+        self.window.clone()
+            .filter(|record| recording.is_selected(*record) && recording.packet_truncated_length(*record) > 0)
+            .count()
+    }
+
+    /// The record within the window that is captured at or after the `timestamp`, or the last record of the
+    /// window when all its records are captured before it. When the records are not in capture order, this is
+    /// the first record in file order that is captured at or after the `timestamp`.
+    pub(crate) fn record_at(&self, recording: &Recording, timestamp: Duration) -> usize {
+        recording.records_before(|captured| captured >= timestamp)
+            .clamp(self.window.start, self.window.end - 1)
+    }
+
+    /// Continues reading at the `record`, which is limited to the window. Fragments read before it are dropped.
+    pub(crate) fn seek(&mut self, record: usize) {
+        self.next_record = record.clamp(self.window.start, self.window.end - 1);
+        self.reassembler = Reassembler::default();
+    }
+
+    /// Continues reading at the start of the window.
+    pub(crate) fn rewind(&mut self) {
+        self.seek(self.window.start);
+    }
+
+    /// Counts the packet last returned by `next` as skipped instead of replayed, e.g. when it has no destination.
+    pub(crate) fn skip(&mut self, packet: &ReplayPacket, reason: SkipReason) {
+        trace!("Skipping packet {}: {reason}.", packet.record);
+        if self.last_counted == Some(packet.record) {
+            self.statistics.replayed -= 1;
+            *self.statistics.skipped.entry(reason).or_insert(0) += 1;
+        }
+    }
+
+    /// Reads up to the next datagram to replay, or returns `None` at the end of the window.
+    pub(crate) fn next(&mut self, recording: &Recording) -> Option<ReplayPacket> {
+        while self.next_record < self.window.end {
+            let record = self.next_record;
+            self.next_record += 1;
+            let counting = record >= self.counted_until;
+            if counting {
+                self.counted_until = record + 1;
+                self.statistics.read += 1;
+            }
+            match self.read(recording, record, counting) {
+                Ok(Some(packet)) => {
+                    self.last_counted = counting.then_some(record);
+                    if counting {
+                        self.statistics.replayed += 1;
+                    }
+                    return Some(packet);
+                }
+                // a fragment of a datagram that is not complete yet
+                Ok(None) => {}
+                Err(reason) => {
+                    trace!("Skipping packet {record}: {reason}.");
+                    if counting {
+                        *self.statistics.skipped.entry(reason).or_insert(0) += 1;
+                    }
+                }
+            }
+        }
+        self.report_incomplete_datagrams();
+        None
+    }
+
+    /// Moves the datagrams of which not all fragments were read into the statistics, once for every datagram.
+    fn report_incomplete_datagrams(&mut self) {
+        for datagram in std::mem::take(&mut self.reassembler).incomplete() {
+            let reported = self.statistics.incomplete_datagrams.iter()
+                .any(|known| known.key == datagram.key && known.first_record == datagram.first_record);
+            if !reported {
+                *self.statistics.skipped.entry(SkipReason::IncompleteDatagram).or_insert(0) += datagram.fragments;
+                self.statistics.incomplete_datagrams.push(datagram);
+            }
+        }
+        self.statistics.incomplete_datagrams.sort_by_key(|datagram| datagram.first_record);
+    }
+
+    /// Dissects the record, and returns the datagram to replay from it. Returns `None` for a fragment that
+    /// does not complete its datagram. The filter matches and truncation are only counted when `counting`.
+    fn read(&mut self, recording: &Recording, record: usize, counting: bool) -> Result<Option<ReplayPacket>, SkipReason> {
+        if !recording.is_selected(record) {
+            return Err(SkipReason::OtherInterface);
+        }
+        let dissection = recording.packet_dissection(record).ok_or(SkipReason::Undecodable)?;
+        match &dissection.network {
+            NetworkHeader::Other { ether_type } => { return Err(SkipReason::NotIp { ether_type: *ether_type }); }
+            network => {
+                if let Some(protocol) = network.upper_layer_protocol().filter(|protocol| !self.protocols.contains(protocol)) {
+                    return Err(SkipReason::NotSelected { protocol });
+                }
+            }
+        }
+
+        let filter = self.filter.as_ref();
+        let matches = |input: FilterInput| filter.is_none_or(|filter| filter.matches(&input));
+        let is_fragment = dissection.network.is_fragment();
+        if !is_fragment && !matches(FilterInput {
+            link: &dissection.link,
+            network: &dissection.network,
+            transport: &dissection.transport,
+            payload: &recording.packet_data(record)[dissection.payload.clone()],
+        }) {
+            return Err(SkipReason::NotMatched);
+        }
+        if !is_fragment && counting {
+            self.statistics.matched += 1;
+        }
+
+        // the length fields of a corrupt packet may claim more data than was sent, so the
+        // missing data is limited to what the capture itself reports as cut off
+        let missing_length = dissection.missing_length.min(recording.packet_truncated_length(record));
+        if missing_length > 0 {
+            if counting {
+                self.statistics.truncated += 1;
+            }
+            if self.truncation == TruncationPolicy::Skip {
+                return Err(SkipReason::Truncated);
+            }
+        }
+        let padding = if self.truncation == TruncationPolicy::Pad { missing_length } else { 0 };
+
+        if let Some(position) = FragmentPosition::of(&dissection.network) {
+            let mut fragment_data = recording.packet_data(record)[dissection.payload.clone()].to_vec();
+            fragment_data.resize(fragment_data.len() + padding, 0);
+            let Some(datagram) = self.reassembler.insert(position, &fragment_data, record, recording.packet_timestamp(record)) else {
+                return Ok(None);
+            };
+            // the extension headers that follow the Fragment header of IPv6 are only dissected after reassembly
+            let (protocol, transport_start) = match &dissection.network {
+                NetworkHeader::Ipv6(_) => { ipv6_upper_layer(position.key.protocol, &datagram).ok_or(SkipReason::Undecodable)? }
+                _ => { (position.key.protocol, 0) }
+            };
+            if !self.protocols.contains(&protocol) {
+                return Err(SkipReason::NotSelected { protocol });
+            }
+            let (payload, transport, _missing) = dissect_transport(Some(protocol), &datagram[transport_start..]);
+            let payload = transport_start + payload.start..transport_start + payload.end;
+            if !matches(FilterInput {
+                link: &dissection.link,
+                network: &dissection.network,
+                transport: &transport,
+                payload: &datagram[payload.clone()],
+            }) {
+                return Err(SkipReason::NotMatched);
+            }
+            if counting {
+                self.statistics.matched += 1;
+            }
+            return Ok(Some(ReplayPacket {
+                record,
+                timestamp: recording.packet_timestamp(record),
+                payload: ReplayPayload::Reassembled(datagram[payload].to_vec()),
+                source: position.key.source,
+                source_port: transport.source_port(),
+                destination: position.key.destination,
+                destination_port: transport.destination_port(),
+            }));
+        }
+
+        // packets without IP addresses are skipped as not IP above
+        let (Some(source), Some(destination)) = (dissection.network.source(), dissection.network.destination()) else {
+            return Err(SkipReason::Undecodable);
+        };
+        let payload = if padding > 0 {
+            let mut data = recording.packet_data(record)[dissection.payload].to_vec();
+            data.resize(data.len() + padding, 0);
+            ReplayPayload::Padded(data)
+        } else { ReplayPayload::Captured(dissection.payload) };
+        Ok(Some(ReplayPacket {
+            record,
+            timestamp: recording.packet_timestamp(record),
+            payload,
+            source,
+            source_port: dissection.transport.source_port(),
+            destination,
+            destination_port: dissection.transport.destination_port(),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+    use super::*;
+
+    /// A pcap file of raw IP records, each given as its captured data and original length.
+    fn pcap(records: &[(&[u8], u32)]) -> Recording {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        // magic number, version 2.4, time zone, accuracy, snap length and link type raw IP
+        let mut data = [0xA1B2C3D4u32.to_le_bytes().to_vec(), 2u16.to_le_bytes().to_vec(), 4u16.to_le_bytes().to_vec(),
+            vec![0; 8], 65535u32.to_le_bytes().to_vec(), 101u32.to_le_bytes().to_vec()].concat();
+        for (index, (captured, original_length)) in records.iter().enumerate() {
+            data.extend([(index as u32).to_le_bytes(), 0u32.to_le_bytes(),
+                (captured.len() as u32).to_le_bytes(), original_length.to_le_bytes()].concat());
+            data.extend_from_slice(captured);
+        }
+        file.write_all(&data).unwrap();
+        Recording::open(file.reopen().unwrap(), None, &mut |_, _| {}).unwrap()
+    }
+
+    #[test]
+    fn counts_truncated_records_of_the_window_without_reading_them() {
+        let recording = pcap(&[(b"complete", 8), (b"trunc", 8)]);
+        let mut playlist = Playlist::new(&recording, vec![17], None, TruncationPolicy::Truncate);
+
+        assert_eq!(playlist.truncated_records(&recording), 1);
+        assert_eq!(playlist.statistics.read, 0);
+
+        playlist.select_window(&recording, None, Some(SeekTarget::Packet(0))).unwrap();
+        assert_eq!(playlist.truncated_records(&recording), 0);
+    }
+
+    /// An IPv6 fragment, of which the Fragment header is followed by the fragmentable part of the datagram.
+    fn ipv6_fragment(offset: u16, more_fragments: bool, data: &[u8]) -> Vec<u8> {
+        let fragment_header = [vec![60, 0], ((offset / 8) << 3 | more_fragments as u16).to_be_bytes().to_vec(), 7u32.to_be_bytes().to_vec()].concat();
+        let source = "fd00::1".parse::<std::net::Ipv6Addr>().unwrap().octets();
+        let destination = "fd00::2".parse::<std::net::Ipv6Addr>().unwrap().octets();
+        [vec![0x60, 0, 0, 0], ((fragment_header.len() + data.len()) as u16).to_be_bytes().to_vec(), vec![44, 64],
+            source.to_vec(), destination.to_vec(), fragment_header, data.to_vec()].concat()
+    }
+
+    #[test]
+    fn dissects_the_extension_headers_after_the_ipv6_fragment_header_after_reassembly() {
+        // a Destination Options header padded to 8 bytes, followed by the UDP header
+        let destination_options = [17, 0, 1, 4, 0, 0, 0, 0];
+        let udp = [5000u16.to_be_bytes(), 3000u16.to_be_bytes(), 24u16.to_be_bytes(), [0, 0]].concat();
+        let first = ipv6_fragment(0, true, &[destination_options.as_slice(), &udp].concat());
+        let last = ipv6_fragment(16, false, b"fragmented data!");
+        let recording = pcap(&[(&first, first.len() as u32), (&last, last.len() as u32)]);
+        let mut playlist = Playlist::new(&recording, vec![17], None, TruncationPolicy::Truncate);
+
+        let packet = playlist.next(&recording).unwrap();
+        assert_eq!((packet.source_port, packet.destination_port), (Some(5000), Some(3000)));
+        assert_eq!(packet.payload(&recording), b"fragmented data!");
+        assert!(playlist.statistics.skipped.is_empty());
+    }
+}
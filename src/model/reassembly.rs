@@ -0,0 +1,230 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::time::Duration;
+use crate::model::packet::NetworkHeader;
+
+/// How long, in capture time, the fragments of a datagram wait for the missing fragments.
+/// Expiring them keeps an IP identification that is reused later on from merging unrelated fragments.
+pub const REASSEMBLY_TIMEOUT : Duration = Duration::from_secs(30);
+
+/// Fragments belong to the same datagram when these fields of their IP headers match.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct FragmentKey {
+    pub source: IpAddr,
+    pub destination: IpAddr,
+    pub protocol: u8,
+    pub identification: u32,
+}
+
+/// The place of a single fragment within its datagram.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct FragmentPosition {
+    pub key: FragmentKey,
+    pub offset: usize,
+    pub more_fragments: bool,
+}
+
+impl FragmentPosition {
+    /// Returns the position of the packet within its datagram, or `None` when the packet is not a fragment.
+    pub fn of(network: &NetworkHeader) -> Option<FragmentPosition> {
+        match network {
+            NetworkHeader::Ipv4(header) if header.more_fragments || header.fragment_offset != 0 => {
+                Some(FragmentPosition {
+                    key: FragmentKey {
+                        source: IpAddr::V4(header.source),
+                        destination: IpAddr::V4(header.destination),
+                        protocol: header.protocol,
+                        identification: header.identification as u32,
+                    },
+                    offset: header.fragment_offset as usize * 8,
+                    more_fragments: header.more_fragments,
+                })
+            }
+            NetworkHeader::Ipv6(header) => {
+                header.fragment
+                    .filter(|fragment| fragment.more_fragments || fragment.fragment_offset != 0)
+                    .map(|fragment| FragmentPosition {
+                        key: FragmentKey {
+                            source: IpAddr::V6(header.source),
+                            destination: IpAddr::V6(header.destination),
+                            protocol: header.protocol,
+                            identification: fragment.identification,
+                        },
+                        offset: fragment.fragment_offset as usize * 8,
+                        more_fragments: fragment.more_fragments,
+                    })
+            }
+            _ => { None }
+        }
+    }
+}
+
+/// A datagram of which not all fragments were captured.
+#[derive(Debug, Clone)]
+pub struct IncompleteDatagram {
+    pub key: FragmentKey,
+    pub first_record: usize,
+    pub fragments: usize,
+    pub received_bytes: usize,
+}
+
+struct PendingDatagram {
+    first_record: usize,
+    first_timestamp: Duration,
+    fragments: Vec<(usize, Vec<u8>)>,
+    total_length: Option<usize>,
+}
+
+impl PendingDatagram {
+    fn is_expired(&self, timestamp: Duration) -> bool {
+        timestamp.saturating_sub(self.first_timestamp) > REASSEMBLY_TIMEOUT
+    }
+
+    fn into_incomplete(self, key: FragmentKey) -> IncompleteDatagram {
+        IncompleteDatagram {
+            key,
+            first_record: self.first_record,
+            fragments: self.fragments.len(),
+            received_bytes: self.fragments.iter().map(|(_, data)| data.len()).sum(),
+        }
+    }
+
+    /// Returns the reassembled data when the fragments cover the whole datagram without gaps.
+    fn reassemble(&mut self) -> Option<Vec<u8>> {
+        let total_length = self.total_length?;
+        self.fragments.sort_by_key(|(offset, _)| *offset);
+
+        let mut covered = 0;
+        for (offset, data) in self.fragments.iter() {
+            if *offset > covered {
+                return None;
+            }
+            covered = covered.max(offset + data.len());
+        }
+        if covered < total_length {
+            return None;
+        }
+
+        let mut datagram = vec![0u8; total_length];
+        for (offset, data) in self.fragments.iter().filter(|(offset, _)| *offset < total_length) {
+            let length = data.len().min(total_length - offset);
+            datagram[*offset..offset + length].copy_from_slice(&data[..length]);
+        }
+        Some(datagram)
+    }
+}
+
+/// Collects IP fragments until all fragments of a datagram are in, or until they expire.
+#[derive(Default)]
+pub struct Reassembler {
+    pending: HashMap<FragmentKey, PendingDatagram>,
+    expired: Vec<IncompleteDatagram>,
+    next_expiry_check: Duration,
+}
+
+impl Reassembler {
+    /// Adds the data of a fragment, taken from the given record and captured at `timestamp`.
+    /// Returns the reassembled payload of the datagram when this fragment completes it.
+    pub fn insert(&mut self, position: FragmentPosition, data: &[u8], record: usize, timestamp: Duration) -> Option<Vec<u8>> {
+        self.expire(timestamp);
+        if self.pending.get(&position.key).is_some_and(|pending| pending.is_expired(timestamp)) {
+            let expired = self.pending.remove(&position.key).expect("the pending datagram was just found");
+            self.expired.push(expired.into_incomplete(position.key));
+        }
+
+        let pending = self.pending.entry(position.key).or_insert_with(|| PendingDatagram {
+            first_record: record,
+            first_timestamp: timestamp,
+            fragments: vec![],
+            total_length: None,
+        });
+        if !position.more_fragments {
+            pending.total_length = Some(position.offset + data.len());
+        }
+        pending.fragments.push((position.offset, data.to_vec()));
+
+        let datagram = pending.reassemble();
+        if datagram.is_some() {
+            self.pending.remove(&position.key);
+        }
+        datagram
+    }
+
+    /// Expires the pending datagrams that waited longer than the timeout. To keep loading linear in the
+    /// number of fragments, all datagrams are checked at most once per timeout; `insert` checks its own datagram.
+    fn expire(&mut self, timestamp: Duration) {
+        if timestamp < self.next_expiry_check {
+            return;
+        }
+        self.next_expiry_check = timestamp + REASSEMBLY_TIMEOUT;
+        let expired_keys: Vec<FragmentKey> = self.pending.iter()
+            .filter(|(_, pending)| pending.is_expired(timestamp))
+            .map(|(key, _)| *key)
+            .collect();
+        for key in expired_keys {
+            let expired = self.pending.remove(&key).expect("the key was just taken from the pending datagrams");
+            self.expired.push(expired.into_incomplete(key));
+        }
+    }
+
+    /// The datagrams that expired or are still missing one or more fragments, in order of their first fragment.
+    pub fn incomplete(self) -> Vec<IncompleteDatagram> {
+        let mut incomplete = self.expired;
+        incomplete.extend(self.pending.into_iter().map(|(key, pending)| pending.into_incomplete(key)));
+        incomplete.sort_by_key(|datagram| datagram.first_record);
+        incomplete
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+    use super::*;
+
+    fn fragment(identification: u32, offset: usize, more_fragments: bool) -> FragmentPosition {
+        FragmentPosition {
+            key: FragmentKey {
+                source: IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)),
+                destination: IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2)),
+                protocol: 17,
+                identification,
+            },
+            offset,
+            more_fragments,
+        }
+    }
+
+    #[test]
+    fn reassembles_fragments_in_any_order() {
+        let mut reassembler = Reassembler::default();
+
+        assert_eq!(reassembler.insert(fragment(1, 8, false), b"world", 0, Duration::ZERO), None);
+        assert_eq!(reassembler.insert(fragment(1, 0, true), b"hello, c", 1, Duration::ZERO).as_deref(), Some(&b"hello, cworld"[..]));
+        assert!(reassembler.incomplete().is_empty());
+    }
+
+    #[test]
+    fn expired_fragments_are_not_merged_with_a_reused_identification() {
+        let mut reassembler = Reassembler::default();
+        let later = REASSEMBLY_TIMEOUT + Duration::from_secs(1);
+
+        assert_eq!(reassembler.insert(fragment(1, 0, true), b"stale!!!", 0, Duration::ZERO), None);
+        assert_eq!(reassembler.insert(fragment(1, 8, false), b"new", 1, later), None);
+        assert_eq!(reassembler.insert(fragment(1, 0, true), b"current!", 2, later).as_deref(), Some(&b"current!new"[..]));
+
+        let incomplete = reassembler.incomplete();
+        assert_eq!(incomplete.len(), 1);
+        assert_eq!((incomplete[0].first_record, incomplete[0].fragments), (0, 1));
+    }
+
+    #[test]
+    fn expires_datagrams_of_other_identifications() {
+        let mut reassembler = Reassembler::default();
+
+        reassembler.insert(fragment(1, 0, true), b"lost....", 0, Duration::ZERO);
+        reassembler.insert(fragment(2, 0, true), b"waiting.", 1, REASSEMBLY_TIMEOUT * 2);
+
+        assert_eq!(reassembler.expired.len(), 1);
+        assert_eq!(reassembler.pending.len(), 1);
+    }
+}
@@ -0,0 +1,63 @@
+use std::fmt::{Display, Formatter};
+
+/// A damaged region of a capture file, found while parsing it.
+/// `offset` is the position in the file where the damage starts, and `record` the index of the
+/// first packet record that is affected, which equals the number of complete records before it.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Damage {
+    pub offset: usize,
+    pub record: usize,
+    pub kind: DamageKind,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum DamageKind {
+    /// The file ends in the middle of a record, e.g. because the capturing process was killed.
+    Truncated { length: usize },
+    /// Corrupt data that was skipped until the next valid record or block.
+    Skipped { length: usize },
+}
+
+impl Damage {
+    /// Builds a damage report for the `input` that remains of the `file`.
+    pub fn at(file: &[u8], input: &[u8], record: usize, kind: DamageKind) -> Damage {
+        Damage {
+            offset: file.len() - input.len(),
+            record,
+            kind,
+        }
+    }
+}
+
+impl DamageKind {
+    /// Classifies a parse error, after which `skip` of the `remaining` bytes were skipped to resync.
+    /// An incomplete record that nothing valid follows is the end of a file that was cut off.
+    pub fn of<E>(err: &nom::Err<E>, skip: usize, remaining: usize) -> DamageKind {
+        if skip == remaining && !matches!(err, nom::Err::Failure(_)) {
+            DamageKind::Truncated { length: skip }
+        } else {
+            DamageKind::Skipped { length: skip }
+        }
+    }
+}
+
+impl Display for Damage {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self.kind {
+            DamageKind::Truncated { length } => {
+                write!(f, "truncated record {} at offset {}, dropped the last {} bytes", self.record + 1, self.offset, length)
+            }
+            DamageKind::Skipped { length } => {
+                write!(f, "corrupt data before record {} at offset {}, skipped {} bytes", self.record + 1, self.offset, length)
+            }
+        }
+    }
+}
+
+/// Searches for the next position in `input` where a valid record starts, as judged by `is_record_start`.
+/// Returns the number of bytes to skip, which is the length of the input when no valid record follows.
+pub fn resync(input: &[u8], is_record_start: impl Fn(&[u8]) -> bool) -> usize {
+    (1..input.len())
+        .find(|skip| is_record_start(&input[*skip..]))
+        .unwrap_or(input.len())
+}
@@ -0,0 +1,260 @@
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::net::IpAddr;
+use std::path::Path;
+use std::str::FromStr;
+use crate::model::{Destination, SourcePort};
+use crate::model::error::RewriteError;
+use crate::model::filter::{Direction, in_network};
+use crate::model::playlist::ReplayPacket;
+
+const RULE_SEPARATOR : &str = "->";
+const SOURCE_PORT_KEYWORD : &str = "from";
+const WILDCARD : &str = "*";
+
+/// Rules that rewrite the destination of the replayed packets, loaded from a file with one rule per line:
+///
+/// ```text
+/// # pattern               -> destination
+/// 239.1.1.1:3000          -> 10.20.0.255:4000
+/// src 10.0.0.0/24         -> 10.20.0.10:*
+/// dst 239.2.0.0/16:*      -> *:5000 from 6000
+/// *                       -> 10.20.0.255:*
+/// ```
+///
+/// A rule may set the source port to send from with `from <port>`, where `from *` keeps the captured source port.
+/// Rules are evaluated in order, and the first rule whose pattern matches a packet decides its destination.
+/// Packets that no rule matches fall through to the `--destination`.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct RewriteRules {
+    pub rules: Vec<RewriteRule>,
+}
+
+/// A single rule. Fields of the `target` that are a wildcard keep the captured destination.
+/// Without a `source_port`, packets are sent from the `--source` port.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct RewriteRule {
+    pub pattern: EndpointPattern,
+    pub target: Destination,
+    pub source_port: Option<SourcePort>,
+}
+
+/// Matches the source or destination endpoint of a packet by network and port, either of which may be a wildcard.
+/// Without a direction, a pattern matches the destination.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub(crate) struct EndpointPattern {
+    pub direction: Direction,
+    pub network: Option<(IpAddr, u8)>,
+    pub port: Option<u16>,
+}
+
+impl RewriteRules {
+    pub(crate) fn load(path: &Path) -> Result<RewriteRules, RewriteError> {
+        let contents = fs::read_to_string(path).map_err(RewriteError::Read)?;
+        contents.parse()
+    }
+
+    /// The first rule that matches the packet.
+    pub(crate) fn rule_for(&self, packet: &ReplayPacket) -> Option<&RewriteRule> {
+        self.rules.iter()
+            .find(|rule| rule.pattern.matches(packet))
+    }
+}
+
+impl FromStr for RewriteRules {
+    type Err = RewriteError;
+
+    fn from_str(contents: &str) -> Result<Self, Self::Err> {
+        let rules = contents.lines().enumerate()
+            .map(|(index, line)| (index + 1, line.split('#').next().unwrap_or_default().trim()))
+            .filter(|(_, line)| !line.is_empty())
+            .map(|(line_number, line)| {
+                line.split_once(RULE_SEPARATOR)
+                    .ok_or_else(|| format!("expected <pattern> {RULE_SEPARATOR} <destination> [{SOURCE_PORT_KEYWORD} <port>]"))
+                    .and_then(|(pattern, target)| {
+                        let (target, source_port) = match target.trim().split_once(char::is_whitespace) {
+                            Some((target, source)) => {
+                                let port = source.trim().strip_prefix(SOURCE_PORT_KEYWORD)
+                                    .ok_or_else(|| format!("expected '{SOURCE_PORT_KEYWORD} <port>' after the destination"))?;
+                                (target, Some(port.trim().parse()?))
+                            }
+                            None => { (target.trim(), None) }
+                        };
+                        Ok(RewriteRule { pattern: pattern.trim().parse()?, target: target.parse()?, source_port })
+                    })
+                    .map_err(|reason| RewriteError::Invalid { line: line_number, reason })
+            })
+            .collect::<Result<Vec<RewriteRule>, RewriteError>>()?;
+        Ok(RewriteRules { rules })
+    }
+}
+
+impl EndpointPattern {
+    fn matches(&self, packet: &ReplayPacket) -> bool {
+        let (address, port) = match self.direction {
+            Direction::Source => { (packet.source, packet.source_port) }
+            Direction::Destination | Direction::Either => { (packet.destination, packet.destination_port) }
+        };
+        self.network.is_none_or(|(network, prefix_length)| in_network(address, network, prefix_length))
+            && self.port.is_none_or(|expected| port == Some(expected))
+    }
+}
+
+/// Parses `[src|dst] <address>[/<prefix>][:<port>]`, where the address and port may be `*`, or a single `*` matching any packet.
+impl FromStr for EndpointPattern {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid pattern '{value}', expected [src|dst] <address>[/<prefix>][:<port>]");
+        let (direction, endpoint) = match value.split_once(char::is_whitespace) {
+            Some(("src", endpoint)) => { (Direction::Source, endpoint.trim()) }
+            Some(("dst", endpoint)) => { (Direction::Destination, endpoint.trim()) }
+            Some(_) => { return Err(invalid()); }
+            None => { (Direction::Destination, value) }
+        };
+
+        // the port separator is ambiguous for IPv6 addresses, which therefore need brackets when a port is given
+        let (address, port) = match endpoint.strip_prefix('[') {
+            Some(bracketed) => {
+                let (address, rest) = bracketed.split_once(']').ok_or_else(invalid)?;
+                let port = match rest {
+                    "" => { None }
+                    rest => { Some(rest.strip_prefix(':').ok_or_else(invalid)?) }
+                };
+                (address, port)
+            }
+            None if endpoint.matches(':').count() == 1 => {
+                endpoint.split_once(':').map(|(address, port)| (address, Some(port))).ok_or_else(invalid)?
+            }
+            None => { (endpoint, None) }
+        };
+
+        let network = if address == WILDCARD { None } else {
+            let (address, prefix_length) = address.split_once('/').map_or((address, None), |(address, prefix)| (address, Some(prefix)));
+            let address = address.parse::<IpAddr>().map_err(|_| invalid())?;
+            let max_prefix_length = if address.is_ipv4() { 32 } else { 128 };
+            let prefix_length = match prefix_length {
+                Some(prefix) => { prefix.parse::<u8>().ok().filter(|prefix| *prefix <= max_prefix_length).ok_or_else(invalid)? }
+                None => { max_prefix_length }
+            };
+            Some((address, prefix_length))
+        };
+        let port = match port {
+            None => { None }
+            Some(port) if port == WILDCARD => { None }
+            Some(port) => { Some(port.parse::<u16>().map_err(|_| invalid())?) }
+        };
+
+        Ok(EndpointPattern { direction, network, port })
+    }
+}
+
+impl Display for EndpointPattern {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.direction)?;
+        match self.network {
+            Some((IpAddr::V6(address), prefix_length)) => { write!(f, "[{address}/{prefix_length}]")? }
+            Some((address, prefix_length)) => { write!(f, "{address}/{prefix_length}")? }
+            None => { write!(f, "{WILDCARD}")? }
+        }
+        match self.port {
+            Some(port) => { write!(f, ":{port}") }
+            None => { write!(f, ":{WILDCARD}") }
+        }
+    }
+}
+
+impl Display for RewriteRule {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {RULE_SEPARATOR} {}", self.pattern, self.target)?;
+        match self.source_port {
+            Some(source_port) => { write!(f, " {SOURCE_PORT_KEYWORD} {source_port}") }
+            None => { Ok(()) }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv4Addr, Ipv6Addr};
+    use crate::model::playlist::ReplayPayload;
+    use super::*;
+
+    fn pattern(value: &str) -> EndpointPattern {
+        value.parse().unwrap_or_else(|err| panic!("{value}: {err}"))
+    }
+
+    fn packet(source: [u8; 4], source_port: u16, destination: [u8; 4], destination_port: u16) -> ReplayPacket {
+        ReplayPacket {
+            record: 0,
+            timestamp: Default::default(),
+            payload: ReplayPayload::Reassembled(vec![]),
+            source: IpAddr::from(source),
+            source_port: Some(source_port),
+            destination: IpAddr::from(destination),
+            destination_port: Some(destination_port),
+        }
+    }
+
+    #[test]
+    fn parses_patterns_with_directions_prefixes_and_wildcards() {
+        let group = IpAddr::V4(Ipv4Addr::new(239, 1, 1, 1));
+        assert_eq!(pattern("239.1.1.1:3000"), EndpointPattern { direction: Direction::Destination, network: Some((group, 32)), port: Some(3000) });
+        assert_eq!(pattern("src 10.0.0.0/24"), EndpointPattern {
+            direction: Direction::Source, network: Some((IpAddr::V4(Ipv4Addr::new(10, 0, 0, 0)), 24)), port: None });
+        assert_eq!(pattern("dst 239.2.0.0/16:*"), pattern("239.2.0.0/16"));
+        assert_eq!(pattern("*"), EndpointPattern { direction: Direction::Destination, network: None, port: None });
+        assert_eq!(pattern("*:3000"), EndpointPattern { direction: Direction::Destination, network: None, port: Some(3000) });
+    }
+
+    #[test]
+    fn needs_brackets_to_give_a_port_with_an_ipv6_address() {
+        let group = IpAddr::V6(Ipv6Addr::new(0xff05, 0, 0, 0, 0, 0, 0, 1));
+        assert_eq!(pattern("[ff05::1]:3000"), EndpointPattern { direction: Direction::Destination, network: Some((group, 128)), port: Some(3000) });
+        assert_eq!(pattern("[ff05::1]"), pattern("ff05::1"));
+        assert_eq!(pattern("[ff05::/16]:*").network, Some((IpAddr::V6(Ipv6Addr::new(0xff05, 0, 0, 0, 0, 0, 0, 0)), 16)));
+        // without brackets, the last group is part of the address rather than a port
+        assert_eq!(pattern("ff05::1:3000").network, Some((IpAddr::V6(Ipv6Addr::new(0xff05, 0, 0, 0, 0, 0, 1, 0x3000)), 128)));
+        assert_eq!(pattern("ff05::1:3000").port, None);
+    }
+
+    #[test]
+    fn rejects_invalid_patterns() {
+        for value in ["any 10.0.0.1", "10.0.0.1/33", "[ff05::1]3000", "[ff05::1", "10.0.0.1:70000", "host:3000", "[ff05::/129]"] {
+            assert!(value.parse::<EndpointPattern>().is_err(), "{value}");
+        }
+    }
+
+    #[test]
+    fn parses_rules_skipping_comments_and_blank_lines() {
+        let rules: RewriteRules = "# pattern -> destination\n\n239.1.1.1:3000 -> 10.20.0.255:4000 # the feed\nsrc 10.0.0.0/24 -> *:5000 from *\n* -> 10.20.0.255:* from 6000\n"
+            .parse().expect("the rules parse");
+        assert_eq!(rules.rules.len(), 3);
+        assert_eq!(rules.rules[0].to_string(), "dst 239.1.1.1/32:3000 -> 10.20.0.255:4000");
+        assert_eq!(rules.rules[1].source_port, Some(SourcePort::Original));
+        assert_eq!(rules.rules[2].source_port, Some(SourcePort::Fixed(6000)));
+    }
+
+    #[test]
+    fn reports_the_line_of_an_invalid_rule() {
+        for (contents, invalid_line) in [
+            ("* -> *:3000\n239.1.1.1:3000 10.20.0.255:4000", 2),
+            ("# comment\n\n* -> *:3000 to 5000", 3),
+            ("* -> 10.20.0.255", 1),
+        ] {
+            match contents.parse::<RewriteRules>() {
+                Err(RewriteError::Invalid { line, .. }) => { assert_eq!(line, invalid_line, "{contents}"); }
+                other => { panic!("{contents}: {other:?}"); }
+            }
+        }
+    }
+
+    #[test]
+    fn the_first_matching_rule_decides() {
+        let rules: RewriteRules = "src 10.0.0.0/24 -> *:5000\n239.1.1.1:3000 -> 10.20.0.255:4000\n".parse().expect("the rules parse");
+        let target = |packet: &ReplayPacket| rules.rule_for(packet).map(|rule| rule.target.to_string());
+        assert_eq!(target(&packet([10, 0, 0, 5], 1000, [239, 1, 1, 1], 3000)), Some("*:5000".to_string()));
+        assert_eq!(target(&packet([10, 0, 1, 5], 1000, [239, 1, 1, 1], 3000)), Some("10.20.0.255:4000".to_string()));
+        assert_eq!(target(&packet([10, 0, 1, 5], 1000, [239, 1, 1, 1], 3001)), None);
+    }
+}
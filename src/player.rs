@@ -1,25 +1,127 @@
+use std::collections::HashSet;
 use std::fmt::{Display, Formatter};
-use std::net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+use std::num::NonZeroUsize;
 use std::sync::mpsc::{Receiver, Sender, TryRecvError};
 use std::thread;
 use std::thread::JoinHandle;
 use std::time::{Duration, Instant};
 
-use log::trace;
+use log::{trace, warn};
+use socket2::{Domain, Protocol, SockRef, Socket, Type};
 
-use crate::model::{Command, Error, ETHERNET_HEADER_LENGTH, Event, IP_HEADER_LENGTH, Recording, UDP_HEADER_LENGTH};
-use crate::model::pcap::{PcapMagicNumber, PcapPacketRecord};
+use crate::clock::{PlaybackClock, TimingStatistics};
+use crate::model::{Command, Destination, Error, Event, PlayerError, Recording, SeekTarget, SourcePort, Speed, SteppedPacket};
+use crate::model::multicast;
+use crate::model::multicast::{InterfaceSelector, MulticastInterface};
+use crate::model::playlist::{ReplayPacket, SkipReason};
+use crate::model::rewrite::RewriteRules;
+use crate::model::playlist::Playlist;
 
 pub struct Player {
     recording: Recording,
-    destination: SocketAddr,
-    source_port: u16,
+    playlist: Playlist,
+    destination: Destination,
+    rewrite_rules: Option<RewriteRules>,
+    source_port: SourcePort,
+    max_sockets: NonZeroUsize,
     ttl: u32,
+    multicast_interface: Option<MulticastInterface>,
+    multicast_loop: bool,
+    broadcast_addresses: Vec<Ipv4Addr>,
+    speed: Speed,
+    looping: Looping,
+    precise_timing: bool,
     state: PlayerState,
+    // the first packet to replay, read while building the player, which playback starts with
+    first_packet: Option<ReplayPacket>,
     cmd_rx: Receiver<Command>,
     event_tx: Sender<Event>,
 }
 
+/// How often the recording is replayed. `iterations` is `None` to replay endlessly.
+/// `gap` is the pause between the last packet of an iteration and the first packet of the next.
+/// With `monotonic` the playback time keeps increasing across iterations, instead of restarting at zero.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub(crate) struct Looping {
+    pub iterations: Option<u32>,
+    pub gap: Duration,
+    pub monotonic: bool,
+}
+
+impl Default for Looping {
+    fn default() -> Self {
+        Self {
+            iterations: Some(1),
+            gap: Duration::ZERO,
+            monotonic: false,
+        }
+    }
+}
+
+impl Looping {
+    /// Whether playback starts over after the given (0-based) iteration.
+    fn repeats_after(&self, iteration: usize) -> bool {
+        self.iterations.is_none_or(|iterations| iteration + 1 < iterations as usize)
+    }
+}
+
+/// The longest the player waits for a packet before checking for commands again.
+const COMMAND_POLL_INTERVAL : Duration = Duration::from_millis(50);
+
+/// The sockets to send from, opened on first use for each address family and source port.
+/// At most `capacity` sockets are kept open; when full, the least recently used socket is closed to make room.
+struct SocketPool {
+    // ordered from least to most recently used
+    sockets: Vec<(SocketAddr, PooledSocket)>,
+    capacity: usize,
+    // the captured source ports that could not be bound, which are only warned about once
+    unavailable_ports: HashSet<u16>,
+}
+
+impl SocketPool {
+    fn new(capacity: NonZeroUsize) -> Self {
+        Self {
+            sockets: Vec::new(),
+            capacity: capacity.get(),
+            unavailable_ports: HashSet::new(),
+        }
+    }
+
+    /// The socket bound to the address, which is opened with `open` when it is not in the pool.
+    fn get_or_open(&mut self, address: SocketAddr, open: impl FnOnce(SocketAddr) -> Result<UdpSocket, PlayerError>) -> Result<&mut PooledSocket, PlayerError> {
+        match self.sockets.iter().position(|(bound_to, _)| *bound_to == address) {
+            Some(index) => {
+                let entry = self.sockets.remove(index);
+                self.sockets.push(entry);
+            }
+            None => {
+                // the socket to make room with is closed first, so that no more than `capacity` sockets are ever open
+                if self.sockets.len() >= self.capacity {
+                    let (closed, _) = self.sockets.remove(0);
+                    trace!("Closing the socket bound to {closed} to open one bound to {address}");
+                }
+                let socket = open(address)?;
+                self.sockets.push((address, PooledSocket { socket, broadcast: false, multicast: false }));
+            }
+        }
+        Ok(self.most_recent())
+    }
+
+    /// The socket that was used last.
+    fn most_recent(&mut self) -> &mut PooledSocket {
+        &mut self.sockets.last_mut().expect("a socket was opened before").1
+    }
+}
+
+/// An open socket, along with the socket options for broadcast and multicast destinations that were applied to it.
+/// These options are applied when the socket first sends to such a destination.
+struct PooledSocket {
+    socket: UdpSocket,
+    broadcast: bool,
+    multicast: bool,
+}
+
 #[derive(Debug, Copy, Clone, PartialEq)]
 pub(crate) enum PlayerState {
     Initial,
@@ -42,63 +144,242 @@ impl Display for PlayerState {
 }
 
 impl Player {
+    /// Plays the recording until the player is quit. Errors end the player, and are reported as an `Event::Error`,
+    /// after the statistics of the playback up to the error.
     pub fn run(&mut self) {
-        const STRIP_HEADERS_INDEX: usize = (ETHERNET_HEADER_LENGTH+IP_HEADER_LENGTH+UDP_HEADER_LENGTH+1) as usize;
+        let mut timing = TimingStatistics::default();
+        if let Err(err) = self.play(&mut timing) {
+            self.report(&timing);
+            let _ = self.event_tx.send(Event::error(err));
+        }
+    }
 
-        let socket = UdpSocket::bind(
-            SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), self.source_port))
-            .expect(format!("Failed to bind socket to port {:?}", self.source_port).as_str());
-        socket.set_broadcast(true).expect("Failed to set socket SO_BROADCAST option.");
-        socket.set_ttl(self.ttl).expect("Failed to set socket TTL value");
+    /// Reports the statistics of the playlist and of the timing so far.
+    fn report(&self, timing: &TimingStatistics) {
+        let _ = self.event_tx.send(Event::PlaylistReport(self.playlist.statistics.clone()));
+        let _ = self.event_tx.send(Event::TimingReport(*timing));
+    }
 
-        let recording = if let Recording::PCAP(pcap) = &self.recording {
-            pcap
+    /// The address to send the packet to: the destination of the first matching rewrite rule, or
+    /// otherwise the `--destination`. Returns `None` when the destination port is not known for the packet.
+    fn destination_of(&self, packet: &ReplayPacket) -> Option<SocketAddr> {
+        self.rewrite_rules.as_ref()
+            .and_then(|rules| rules.rule_for(packet))
+            .map_or(self.destination, |rule| rule.target)
+            .resolve(packet.destination, packet.destination_port)
+    }
+
+    /// The socket to send the packet to `destination` from, which is opened when it is not in the pool. It is bound to
+    /// the unspecified address of the same family, with the source port of the first matching rewrite rule, or otherwise
+    /// the `--source` port. A captured source port that cannot be bound, e.g. because it is privileged or in use,
+    /// is replaced with an ephemeral port, so that a single port does not end playback.
+    fn socket_for<'a>(&self, sockets: &'a mut SocketPool, packet: &ReplayPacket, destination: SocketAddr) -> Result<&'a mut PooledSocket, PlayerError> {
+        let source_port = self.rewrite_rules.as_ref()
+            .and_then(|rules| rules.rule_for(packet))
+            .and_then(|rule| rule.source_port)
+            .unwrap_or(self.source_port);
+        let address = if destination.is_ipv4() { IpAddr::V4(Ipv4Addr::UNSPECIFIED) } else { IpAddr::V6(Ipv6Addr::UNSPECIFIED) };
+        let address = SocketAddr::new(address, source_port.resolve(packet.source_port));
+        let mut bind_error = None;
+        sockets.get_or_open(address, |address| {
+            match self.open_socket(address) {
+                Err(PlayerError::Bind { source, .. }) if source_port == SourcePort::Original && address.port() != 0 => {
+                    bind_error = Some(source);
+                    self.open_socket(SocketAddr::new(address.ip(), 0))
+                }
+                result => { result }
+            }
+        })?;
+        if let Some(source) = bind_error {
+            if sockets.unavailable_ports.insert(address.port()) {
+                warn!("Cannot send from the captured source port {} ({source}), sending from an ephemeral port instead.", address.port());
+            }
+        }
+        Ok(sockets.most_recent())
+    }
+
+    /// Opens a socket bound to the address, with the TTL or hop limit for unicast destinations.
+    fn open_socket(&self, address: SocketAddr) -> Result<UdpSocket, PlayerError> {
+        if address.is_ipv4() {
+            let socket = UdpSocket::bind(address)
+                .map_err(|source| PlayerError::Bind { address, source })?;
+            socket.set_ttl(self.ttl)
+                .map_err(|source| PlayerError::SocketOption { option: "IP_TTL", source })?;
+            Ok(socket)
         } else {
-            let _ = self.event_tx.send(
-                Event::error(
-                    Error::FileTypeNotSupported("Files other than .pcap are currently not supported.".to_string()
-            )));
-            return;
-        };
-        trace!("{:?}", recording.header);
+            let socket = Socket::new(Domain::IPV6, Type::DGRAM, Some(Protocol::UDP))
+                .map_err(|source| PlayerError::Bind { address, source })?;
+            // an IPv6 only socket can share the source port with the IPv4 socket
+            socket.set_only_v6(true)
+                .map_err(|source| PlayerError::SocketOption { option: "IPV6_V6ONLY", source })?;
+            socket.bind(&address.into())
+                .map_err(|source| PlayerError::Bind { address, source })?;
+            socket.set_unicast_hops_v6(self.ttl)
+                .map_err(|source| PlayerError::SocketOption { option: "IPV6_UNICAST_HOPS", source })?;
+            Ok(socket.into())
+        }
+    }
 
-        let first_ts = duration_from_timestamp(&recording.header.magic_number, &recording.packets.first().unwrap());
-        let last_ts = duration_from_timestamp(&recording.header.magic_number, &recording.packets.last().unwrap());
-        let total_duration = last_ts - first_ts;
+    /// Applies the socket options that a broadcast or multicast `destination` needs, the first time the socket sends
+    /// to one: broadcasting is only enabled for broadcast destinations, and the multicast TTL, loopback and interface
+    /// only set for multicast destinations, so that an interface without an IPv4 address only fails IPv4 multicast.
+    fn prepare_socket(&self, pooled: &mut PooledSocket, destination: IpAddr) -> Result<(), PlayerError> {
+        let socket = &pooled.socket;
+        match destination {
+            IpAddr::V4(address) if address.is_multicast() && !pooled.multicast => {
+                socket.set_multicast_ttl_v4(self.ttl)
+                    .map_err(|source| PlayerError::SocketOption { option: "IP_MULTICAST_TTL", source })?;
+                socket.set_multicast_loop_v4(self.multicast_loop)
+                    .map_err(|source| PlayerError::SocketOption { option: "IP_MULTICAST_LOOP", source })?;
+                if let Some(interface) = &self.multicast_interface {
+                    let interface_address = interface.ipv4
+                        .ok_or_else(|| PlayerError::NoInterfaceAddress(interface.name.clone()))?;
+                    SockRef::from(socket).set_multicast_if_v4(&interface_address)
+                        .map_err(|source| PlayerError::SocketOption { option: "IP_MULTICAST_IF", source })?;
+                }
+                pooled.multicast = true;
+            }
+            IpAddr::V4(address) if self.is_broadcast(address) && !pooled.broadcast => {
+                socket.set_broadcast(true)
+                    .map_err(|source| PlayerError::SocketOption { option: "SO_BROADCAST", source })?;
+                pooled.broadcast = true;
+            }
+            IpAddr::V6(address) if address.is_multicast() && !pooled.multicast => {
+                let socket = SockRef::from(socket);
+                socket.set_multicast_hops_v6(self.ttl)
+                    .map_err(|source| PlayerError::SocketOption { option: "IPV6_MULTICAST_HOPS", source })?;
+                socket.set_multicast_loop_v6(self.multicast_loop)
+                    .map_err(|source| PlayerError::SocketOption { option: "IPV6_MULTICAST_LOOP", source })?;
+                // without an index, the interface is left to the routing table
+                if let Some(index) = self.multicast_interface.as_ref().and_then(|interface| interface.index) {
+                    socket.set_multicast_if_v6(index)
+                        .map_err(|source| PlayerError::SocketOption { option: "IPV6_MULTICAST_IF", source })?;
+                }
+                pooled.multicast = true;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Whether the address is the limited broadcast address, or the broadcast address of one of the host's networks.
+    fn is_broadcast(&self, address: Ipv4Addr) -> bool {
+        address.is_broadcast() || self.broadcast_addresses.contains(&address)
+    }
+
+    /// The next packet of the playlist that has a destination to send it to. The packets without one are skipped.
+    fn next_packet(&mut self) -> Option<ReplayPacket> {
+        loop {
+            let packet = self.playlist.next(&self.recording)?;
+            if self.destination_of(&packet).is_some() {
+                return Some(packet);
+            }
+            self.playlist.skip(&packet, SkipReason::NoDestinationPort);
+        }
+    }
+
+    fn play(&mut self, timing: &mut TimingStatistics) -> Result<(), Error> {
+        let mut sockets = SocketPool::new(self.max_sockets);
+        // the packet to send next, read ahead of sending it
+        let mut upcoming = self.first_packet.take().or_else(|| self.next_packet());
+        // the socket for the first packet is opened up front, so that a source port in use is reported right away
+        if let Some(first_packet) = &upcoming {
+            let destination = self.destination_of(first_packet).expect("packets without a destination are skipped");
+            let pooled = self.socket_for(&mut sockets, first_packet, destination)?;
+            self.prepare_socket(pooled, destination.ip())?;
+        }
+
+        match &self.recording {
+            Recording::PCAP(pcap) => { trace!("{:?}", pcap.header); }
+            Recording::PCAPNG(pcapng) => { trace!("{:?}", pcapng.sections.iter().map(|section| &section.header).collect::<Vec<_>>()); }
+        }
+
+        // positions count the records of the window, as the packets to replay are not known up front
+        let window = self.playlist.window();
+        let max_position = window.len() - 1;
+        let position_of = |packet: Option<&ReplayPacket>| packet.map_or(max_position, |packet| packet.record - window.start);
+        let first_ts = upcoming.as_ref().map_or(Duration::ZERO, |packet| packet.timestamp);
+        let last_ts = self.recording.packet_timestamp(window.end - 1);
+        let total_duration = last_ts.saturating_sub(first_ts);
 
-        let mut packets = recording.packets.iter().enumerate();
         // let mut terminal_synced = false;
-        let mut previous_ts = first_ts.clone();
+        let mut previous_ts = first_ts;
         let mut playback_elapsed = previous_ts - first_ts;
         // let mut previous_state = self.state.clone();
+        let mut iteration = 0;
+        // the playback time at which the current iteration started, which stays zero unless looping monotonically
+        let mut iteration_start = Duration::ZERO;
+        let mut pending_steps = 0;
+        let mut clock = PlaybackClock::new(self.precise_timing);
+        // whether the statistics were reported since the last packet was sent, when playback finished
+        let mut reported = false;
 
         let _ = self.event_tx.send(Event::PlayerReady);
         let _ = self.event_tx.send(Event::state_event(PlayerState::Initial));
-        let _ = self.event_tx.send(Event::position_event(0,recording.packets.len(),playback_elapsed, total_duration));
-
-        let mut loop_time_start : Option<Instant> = None;
+        let _ = self.event_tx.send(Event::position_event(position_of(upcoming.as_ref()), max_position, playback_elapsed, total_duration, iteration, self.playlist.statistics.matched));
+        let _ = self.event_tx.send(Event::PlayerSpeedChanged(self.speed));
 
         loop {
             // receive any command and update state
             if let Some(new_state) = match self.cmd_rx.try_recv() {
-                Ok(Command::Play) => { if self.state == PlayerState::Initial {
-                }
+                Ok(Command::Play) => {
+                    pending_steps = 0;
                     Some(PlayerState::Playing)
                 }
                 Ok(Command::Pause) => {
+                    // on resuming, the next packet waits for its recorded interval again
+                    clock.reset();
                     Some(PlayerState::Paused)
                 }
+                // stepping is only possible while paused, and pauses a player that has not started yet
+                Ok(Command::Step(steps)) => {
+                    match self.state {
+                        PlayerState::Paused => { pending_steps = steps; None }
+                        PlayerState::Initial => { pending_steps = steps; Some(PlayerState::Paused) }
+                        _ => { None }
+                    }
+                }
                 Ok(Command::Rewind) => {
-                    packets = recording.packets.iter().enumerate();
-                    previous_ts = duration_from_timestamp(&recording.header.magic_number, &recording.packets.first().unwrap());
+                    self.playlist.rewind();
+                    upcoming = self.next_packet();
+                    previous_ts = first_ts;
                     playback_elapsed = Duration::new(0,0);
+                    iteration = 0;
+                    iteration_start = Duration::ZERO;
+                    pending_steps = 0;
+                    clock.reset();
                     let _ = self.event_tx.send(
                         Event::position_event(
-                            0 ,packets.len(),
-                            playback_elapsed.clone(), total_duration.clone()));
+                            position_of(upcoming.as_ref()), max_position,
+                            playback_elapsed, total_duration, iteration, self.playlist.statistics.matched));
                     // self.progress_bar.reset();
                     Some(PlayerState::Initial)
                 }
+                Ok(Command::Seek(target)) => {
+                    let record = match target {
+                        SeekTarget::Packet(record) => { record }
+                        SeekTarget::Offset { offset, backward: false } => { self.playlist.record_at(&self.recording, previous_ts + offset) }
+                        SeekTarget::Offset { offset, backward: true } => { self.playlist.record_at(&self.recording, previous_ts.saturating_sub(offset)) }
+                        SeekTarget::Timestamp(timestamp) => { self.playlist.record_at(&self.recording, timestamp) }
+                    };
+                    self.playlist.seek(record);
+                    upcoming = self.next_packet();
+                    // the packet sought to is sent right away, the ones after it keep their recorded timing
+                    previous_ts = upcoming.as_ref().map_or(last_ts, |packet| packet.timestamp);
+                    playback_elapsed = iteration_start + previous_ts.saturating_sub(first_ts);
+                    clock.reset();
+                    let _ = self.event_tx.send(
+                        Event::position_event(
+                            position_of(upcoming.as_ref()), max_position,
+                            playback_elapsed, total_duration, iteration, self.playlist.statistics.matched));
+                    None
+                }
+                Ok(Command::Speed(speed)) => {
+                    clock.change_speed(self.speed, speed);
+                    self.speed = speed;
+                    let _ = self.event_tx.send(Event::PlayerSpeedChanged(speed));
+                    None
+                }
                 Ok(Command::Quit) => { Some(PlayerState::Quit) }
                 Ok(Command::Unspecified) => { None } // no-op
                 // Ok(Command::SyncTerm) => {
@@ -107,8 +388,7 @@ impl Player {
                 // } // no-op
                 Err(TryRecvError::Empty) => { None } // no-op
                 Err(TryRecvError::Disconnected) => {
-                    let _ = self.event_tx.send(Event::error(Error::CommandChannelError)).unwrap();
-                    Some(PlayerState::Quit)
+                    return Err(Error::CommandChannel);
                 }
             } {
                 let _ = self.event_tx.send(Event::state_event(new_state));
@@ -124,43 +404,89 @@ impl Player {
                     // self.progress_bar.set_message(format!("{}", self.state));
                 } //} // no-op
                 PlayerState::Playing => {
-                    if let Some((i, packet)) = packets.next() {
-                        let current_ts = duration_from_timestamp(&recording.header.magic_number, &packet);
-                        let ts_duration = current_ts.saturating_sub(previous_ts);
-
-                        let loop_duration = if let Some(start) = loop_time_start {
-                            start.elapsed()
-                        } else { Duration::new(0, 0) };
+                    if let Some(current_ts) = upcoming.as_ref().map(|packet| packet.timestamp) {
 
-                        std::thread::sleep(ts_duration.saturating_sub(loop_duration));
-
-                        loop_time_start = Some(Instant::now());
+                        if let Some(deadline) = clock.deadline(current_ts, previous_ts, self.speed) {
+                            // a packet far ahead is waited for in steps, to keep handling commands meanwhile
+                            if deadline.saturating_duration_since(Instant::now()) > COMMAND_POLL_INTERVAL {
+                                std::thread::sleep(COMMAND_POLL_INTERVAL);
+                                continue;
+                            }
+                            clock.wait_until(deadline);
+                            timing.record(Instant::now().saturating_duration_since(deadline));
+                        }
+                        let packet = upcoming.take().expect("the upcoming packet was just checked");
 
                         previous_ts = current_ts;
-                        playback_elapsed = current_ts - first_ts;
+                        playback_elapsed = iteration_start + current_ts.saturating_sub(first_ts);
 
                         // self.progress_bar.set_position((i+1) as u64);
                         let _ = self.event_tx.send(Event::position_event(
-                            i,
-                            recording.packets.len(),
-                            playback_elapsed.clone(),
-                            total_duration.clone()
+                            position_of(Some(&packet)),
+                            max_position,
+                            playback_elapsed,
+                            total_duration,
+                            iteration,
+                            self.playlist.statistics.matched
                         ));
 
-                        let _bytes_send = socket.send_to(
-                            &packet.packet_data.as_slice()[STRIP_HEADERS_INDEX..],
-                            self.destination)
-                            .expect("Could not send packet");
+                        let _bytes_send = self.send(&mut sockets, &packet)?;
+                        reported = false;
+                        upcoming = self.next_packet();
+                    } else if self.looping.repeats_after(iteration) {
+                        iteration += 1;
+                        if self.looping.monotonic {
+                            iteration_start += total_duration + self.looping.gap;
+                        }
+                        self.playlist.rewind();
+                        upcoming = self.next_packet();
+                        previous_ts = first_ts;
+                        clock.anchor(Instant::now() + self.looping.gap, first_ts);
                     } else {
                         // self.progress_bar.finish();
                         let _ = self.event_tx.send(Event::state_event(PlayerState::Finished));
                         self.state = PlayerState::Finished;
+                        self.report(timing);
+                        reported = true;
+                    }
+                }
+                PlayerState::Paused if pending_steps > 0 => {
+                    pending_steps -= 1;
+                    if let Some(packet) = upcoming.take() {
+                        previous_ts = packet.timestamp;
+                        playback_elapsed = iteration_start + previous_ts.saturating_sub(first_ts);
+
+                        let _ = self.event_tx.send(Event::position_event(
+                            position_of(Some(&packet)),
+                            max_position,
+                            playback_elapsed,
+                            total_duration,
+                            iteration,
+                            self.playlist.statistics.matched
+                        ));
+                        let length = self.send(&mut sockets, &packet)?;
+                        reported = false;
+                        upcoming = self.next_packet();
+                        let _ = self.event_tx.send(Event::PacketStepped(SteppedPacket {
+                            index: packet.record,
+                            timestamp: packet.timestamp,
+                            length,
+                        }));
+                    } else {
+                        pending_steps = 0;
+                        let _ = self.event_tx.send(Event::state_event(PlayerState::Finished));
+                        self.state = PlayerState::Finished;
+                        self.report(timing);
+                        reported = true;
                     }
                 }
                 PlayerState::Paused => { /*if terminal_synced { self.progress_bar.tick() }*/ } // no-op
                 PlayerState::Finished => { /*if terminal_synced { self.progress_bar.tick() }*/ } // no-op
                 PlayerState::Quit => {
-                    break;
+                    if !reported {
+                        self.report(timing);
+                    }
+                    return Ok(());
                 }
             }
             // if previous_state != self.state || self.state == PlayerState::Playing {
@@ -170,38 +496,55 @@ impl Player {
         }
     }
 
+    /// Sends the payload of the packet to its destination, returning the number of bytes sent.
+    fn send(&self, sockets: &mut SocketPool, packet: &ReplayPacket) -> Result<usize, PlayerError> {
+        let destination = self.destination_of(packet)
+            .expect("packets without a destination are skipped");
+        let pooled = self.socket_for(sockets, packet, destination)?;
+        self.prepare_socket(pooled, destination.ip())?;
+        pooled.socket.send_to(
+            packet.payload(&self.recording),
+            destination)
+            .map_err(|source| PlayerError::Send {
+                record: packet.record,
+                destination,
+                source,
+            })
+    }
+
     pub(crate) fn builder() -> PlayerBuilder {
         PlayerBuilder {
             recording: None,
+            playlist: None,
             destination: None,
+            rewrite_rules: None,
             source_port: None,
+            max_sockets: None,
             ttl: None,
+            multicast_interface: None,
+            multicast_loop: None,
+            speed: None,
+            looping: None,
+            precise_timing: None,
             cmd_rx: None,
             event_tx: None,
         }
     }
 }
 
-fn duration_from_timestamp(mode: &PcapMagicNumber, packet: &PcapPacketRecord) -> Duration {
-    let (fraction, overflow) = match mode {
-        PcapMagicNumber::LeMicros => {
-            packet.ts_secs_fraction.overflowing_mul(1_000)
-        }
-        PcapMagicNumber::BeNanos => { (packet.ts_secs_fraction, false) }
-    };
-    let seconds = if overflow {
-        packet.ts_secs + 1
-    } else {
-        packet.ts_secs
-    } as u64;
-    Duration::new(seconds, fraction)
-}
-
 pub(crate) struct PlayerBuilder {
     recording: Option<Recording>,
-    destination: Option<SocketAddr>,
-    source_port: Option<u16>,
+    playlist: Option<Playlist>,
+    destination: Option<Destination>,
+    rewrite_rules: Option<RewriteRules>,
+    source_port: Option<SourcePort>,
+    max_sockets: Option<NonZeroUsize>,
     ttl: Option<u32>,
+    multicast_interface: Option<InterfaceSelector>,
+    multicast_loop: Option<bool>,
+    speed: Option<Speed>,
+    looping: Option<Looping>,
+    precise_timing: Option<bool>,
     cmd_rx: Option<Receiver<Command>>,
     event_tx: Option<Sender<Event>>,
 }
@@ -212,20 +555,39 @@ impl PlayerBuilder {
         self
     }
 
-    pub(crate) fn destination(self, destination: SocketAddr) -> Self {
+    pub(crate) fn playlist(mut self, playlist : Playlist) -> Self {
+        self.playlist = Some(playlist);
+        self
+    }
+
+    pub(crate) fn destination(self, destination: Destination) -> Self {
         Self {
             destination : Some(destination),
             ..self
         }
     }
 
-    pub(crate) fn source_port(self, source_port: u16) -> Self {
+    pub(crate) fn rewrite_rules(self, rewrite_rules: RewriteRules) -> Self {
+        Self {
+            rewrite_rules : Some(rewrite_rules),
+            ..self
+        }
+    }
+
+    pub(crate) fn source_port(self, source_port: SourcePort) -> Self {
         Self {
             source_port : Some(source_port),
             ..self
         }
     }
 
+    pub(crate) fn max_sockets(self, max_sockets: NonZeroUsize) -> Self {
+        Self {
+            max_sockets : Some(max_sockets),
+            ..self
+        }
+    }
+
     pub(crate) fn ttl(self, ttl: u32) -> Self {
         Self {
             ttl : Some(ttl),
@@ -233,6 +595,41 @@ impl PlayerBuilder {
         }
     }
 
+    pub(crate) fn multicast_interface(self, multicast_interface: InterfaceSelector) -> Self {
+        Self {
+            multicast_interface : Some(multicast_interface),
+            ..self
+        }
+    }
+
+    pub(crate) fn multicast_loop(self, multicast_loop: bool) -> Self {
+        Self {
+            multicast_loop : Some(multicast_loop),
+            ..self
+        }
+    }
+
+    pub(crate) fn speed(self, speed: Speed) -> Self {
+        Self {
+            speed : Some(speed),
+            ..self
+        }
+    }
+
+    pub(crate) fn looping(self, looping: Looping) -> Self {
+        Self {
+            looping : Some(looping),
+            ..self
+        }
+    }
+
+    pub(crate) fn precise_timing(self, precise_timing: bool) -> Self {
+        Self {
+            precise_timing : Some(precise_timing),
+            ..self
+        }
+    }
+
     pub(crate) fn cmd_rx(self, cmd_rx: Receiver<Command>) -> Self {
         Self {
             cmd_rx : Some(cmd_rx),
@@ -248,23 +645,43 @@ impl PlayerBuilder {
     }
 
     pub(crate) fn build(self) -> Result<JoinHandle<()>, Error> {
-        if self.recording.is_none() ||
-            self.destination.is_none() ||
-            self.source_port.is_none() ||
-            self.ttl.is_none() ||
-            self.cmd_rx.is_none() ||
-            self.event_tx.is_none() {
-            return Err(Error::PlayerInitError)
-        }
+        let recording = self.recording.ok_or(PlayerError::MissingSetting("recording"))?;
+        let playlist = self.playlist.ok_or(PlayerError::MissingSetting("playlist"))?;
         let mut player = Player {
-            recording: self.recording.unwrap(),
-            destination: self.destination.unwrap(),
-            source_port: self.source_port.unwrap(),
-            ttl: self.ttl.unwrap(),
+            recording,
+            playlist,
+            destination: self.destination.ok_or(PlayerError::MissingSetting("destination"))?,
+            rewrite_rules: self.rewrite_rules,
+            source_port: self.source_port.ok_or(PlayerError::MissingSetting("source port"))?,
+            max_sockets: self.max_sockets.ok_or(PlayerError::MissingSetting("max sockets"))?,
+            ttl: self.ttl.ok_or(PlayerError::MissingSetting("ttl"))?,
+            // without an interface, multicast packets leave through the interface the routing table picks
+            multicast_interface: self.multicast_interface.as_ref().map(InterfaceSelector::resolve).transpose()?,
+            // the operating system loops multicast packets back to local listeners by default
+            multicast_loop: self.multicast_loop.unwrap_or(true),
+            broadcast_addresses: multicast::broadcast_addresses().unwrap_or_else(|err| {
+                warn!("Only 255.255.255.255 is recognised as a broadcast destination, {err}.");
+                vec![]
+            }),
+            speed: self.speed.ok_or(PlayerError::MissingSetting("speed"))?,
+            // without looping the recording is played once
+            looping: self.looping.unwrap_or_default(),
+            precise_timing: self.precise_timing.unwrap_or_default(),
             state: PlayerState::Initial,
-            cmd_rx: self.cmd_rx.unwrap(),
-            event_tx: self.event_tx.unwrap(),
+            first_packet: None,
+            cmd_rx: self.cmd_rx.ok_or(PlayerError::MissingSetting("command channel"))?,
+            event_tx: self.event_tx.ok_or(PlayerError::MissingSetting("event channel"))?,
         };
+        // the recording is read once up to the first packet to replay, and reported when there is none
+        player.first_packet = player.next_packet();
+        if player.first_packet.is_none() {
+            let _ = player.event_tx.send(Event::PlaylistReport(player.playlist.statistics.clone()));
+            // packets without a destination port are skipped while playing, but at least one must have one
+            return Err(if player.playlist.statistics.skipped.contains_key(&SkipReason::NoDestinationPort) {
+                Error::SelectPackets(
+                    "none of the packets has a captured destination port, provide one with --destination or a rewrite rule".to_string())
+            } else { PlayerError::EmptyPlaylist.into() });
+        }
         Ok(thread::spawn(move || {
             player.run();
         }))